@@ -0,0 +1,55 @@
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use matrix_transposer::cancel::Cancellation;
+use matrix_transposer::generate::Generator;
+use matrix_transposer::progress::Silent;
+use matrix_transposer::workspace::Workspace;
+use matrix_transposer::{Dimensions, Strategy, setup_file};
+use std::hint::black_box;
+
+/// Small sizes fit in cache, the medium ones are big enough that the on-disk
+/// strategies start paying for their syscalls without making a single
+/// criterion sample take minutes.
+const LOG2_SIZES: [u32; 4] = [10, 13, 16, 19];
+
+/// The naive on-disk strategy does one syscall per byte, so it's left out of
+/// sizes above this.
+const ON_DISK_MAX_LOG2_SIZE: u32 = 16;
+
+fn bench_strategies(c: &mut Criterion) {
+    let workspace = Workspace::new(std::env::temp_dir().join("matrix_transposer_bench"), None);
+    workspace.create().unwrap();
+
+    let mut group = c.benchmark_group("transpose");
+    group.sample_size(10);
+    for log2_size in LOG2_SIZES {
+        let dims = Dimensions::from_log2_size(log2_size);
+        let input_path = workspace.dir.join(format!("bench_input_{log2_size}.md"));
+        setup_file(dims, &Generator::default(), &input_path, &Silent).unwrap();
+        group.throughput(Throughput::Bytes(dims.size));
+
+        for strategy in Strategy::ALL {
+            if strategy == Strategy::OnDisk && log2_size > ON_DISK_MAX_LOG2_SIZE {
+                continue;
+            }
+            group.bench_with_input(
+                BenchmarkId::new(strategy.name(), log2_size),
+                &input_path,
+                |b, input_path| {
+                    b.iter(|| {
+                        black_box(
+                            strategy
+                                .run(dims, input_path, &workspace, &Silent, &Cancellation::new())
+                                .unwrap(),
+                        )
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+
+    std::fs::remove_dir_all(&workspace.dir).unwrap();
+}
+
+criterion_group!(benches, bench_strategies);
+criterion_main!(benches);
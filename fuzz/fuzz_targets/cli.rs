@@ -0,0 +1,23 @@
+#![no_main]
+
+use clap::Parser;
+use libfuzzer_sys::fuzz_target;
+use matrix_transposer::cli::{Cli, validate};
+
+// Whatever the command line is, parsing and checking it has to come back as
+// an error or as dimensions that add up, never as a panic.
+fuzz_target!(|args: Vec<String>| {
+    let Ok(cli) = Cli::try_parse_from(std::iter::once("matrix_transposer".to_string()).chain(args))
+    else {
+        return;
+    };
+    if cli.command.is_some() {
+        return;
+    }
+    if let Ok((dims, _, _)) = validate(&cli) {
+        assert_eq!(
+            dims.rows as u128 * dims.cols as u128 * dims.elem_size as u128,
+            dims.size as u128
+        );
+    }
+});
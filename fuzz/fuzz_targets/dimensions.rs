@@ -0,0 +1,29 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use matrix_transposer::Dimensions;
+
+// Everything the CLI turns its numeric arguments into dimensions with: a
+// bad argument has to come back as an error, never as a panic or as
+// dimensions that don't add up.
+fuzz_target!(|args: (u32, usize, u64, usize)| {
+    let (log2_size, elem_size, input_len, cols) = args;
+
+    if let Ok(dims) = Dimensions::generated(log2_size, elem_size) {
+        assert_eq!(dims.size, 2u64.pow(log2_size));
+        assert!(dims.rows >= dims.cols);
+        assert_eq!(
+            dims.rows as u64 * dims.cols as u64 * dims.elem_size as u64,
+            dims.size
+        );
+    }
+
+    if let Ok(dims) = Dimensions::of_len(input_len, cols, elem_size) {
+        assert_eq!(dims.size, input_len);
+        assert_eq!((dims.cols, dims.elem_size), (cols, elem_size));
+        assert_eq!(
+            dims.rows as u128 * dims.cols as u128 * dims.elem_size as u128,
+            input_len as u128
+        );
+    }
+});
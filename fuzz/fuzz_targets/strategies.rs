@@ -0,0 +1,68 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use matrix_transposer::cancel::Cancellation;
+use matrix_transposer::progress::Silent;
+use matrix_transposer::workspace::Workspace;
+use matrix_transposer::{Dimensions, Strategy, reference_transpose};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+/// Small enough that the naive on-disk strategy and the join strategy's one
+/// file per column stay fast.
+const MAX_SIDE: usize = 24;
+const MAX_ELEM_SIZE: usize = 8;
+
+/// A directory on tmpfs where there is one, so runs don't wear out a disk.
+/// Every fuzzing process gets its own, so they can run with `-jobs`.
+fn workspace() -> Workspace {
+    let shm = PathBuf::from("/dev/shm");
+    let root = if shm.is_dir() {
+        shm
+    } else {
+        std::env::temp_dir()
+    };
+    let dir = root.join(format!("matrix_transposer_fuzz_{}", std::process::id()));
+    Workspace::new(&dir, Some(dir.clone()))
+}
+
+static WORKSPACE: LazyLock<Workspace> = LazyLock::new(|| {
+    let workspace = workspace();
+    workspace.create().unwrap();
+    workspace
+});
+
+fuzz_target!(|data: &[u8]| {
+    let [rows, cols, elem_size, contents @ ..] = data else {
+        return;
+    };
+    let dims = Dimensions::new(*rows as usize % MAX_SIDE + 1, *cols as usize % MAX_SIDE + 1)
+        .with_elem_size(*elem_size as usize % MAX_ELEM_SIZE + 1);
+    // repeat the rest of the fuzz input to fill the matrix
+    let input: Vec<u8> = contents
+        .iter()
+        .copied()
+        .chain(std::iter::once(0))
+        .cycle()
+        .take(dims.size as usize)
+        .collect();
+    let workspace = &*WORKSPACE;
+    let input_path = workspace.input_path();
+    std::fs::write(&input_path, &input).unwrap();
+
+    let expected = reference_transpose(dims, &input);
+    for strategy in Strategy::ALL {
+        let (mut output, _) = strategy
+            .run(dims, &input_path, workspace, &Silent, &Cancellation::new()).unwrap();
+        let mut actual = Vec::new();
+        output.seek(SeekFrom::Start(0)).unwrap();
+        output.read_to_end(&mut actual).unwrap();
+        std::fs::remove_file(workspace.output_path(strategy)).unwrap();
+        assert!(
+            actual == expected,
+            "{} got a different transpose of a {dims:?} input",
+            strategy.name()
+        );
+    }
+});
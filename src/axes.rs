@@ -0,0 +1,203 @@
+use anyhow::{Result, ensure};
+use serde::{Deserialize, Serialize};
+
+/// The most axes an array can have, which keeps [`Axes`] `Copy`.
+pub const MAX_AXES: usize = 8;
+
+/// The shape of a row-major N-d array, and the order its axes go in once
+/// it's permuted: axis `k` of the output is axis `permutation[k]` of the
+/// input, like numpy's `transpose(permutation)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Axes {
+    ndim: usize,
+    shape: [usize; MAX_AXES],
+    permutation: [usize; MAX_AXES],
+}
+
+/// How to go along one axis of the input: how many elements it has, and how
+/// far apart they are in the input and in the output, in elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Walk {
+    pub len: usize,
+    pub input_stride: u64,
+    pub output_stride: u64,
+}
+
+impl Axes {
+    pub fn new(shape: &[usize], permutation: &[usize]) -> Result<Self> {
+        let ndim = shape.len();
+        ensure!(
+            permutation.len() == ndim,
+            "a permutation of {} axes doesn't fit a shape with {ndim}",
+            permutation.len()
+        );
+        ensure!(
+            ndim <= MAX_AXES,
+            "arrays can have up to {MAX_AXES} axes, not {ndim}"
+        );
+        ensure!(
+            shape.iter().all(|len| *len > 0),
+            "the axes of {shape:?} can't be empty"
+        );
+        let mut seen = [false; MAX_AXES];
+        for &axis in permutation {
+            ensure!(
+                axis < ndim && !std::mem::replace(&mut seen[axis], true),
+                "{permutation:?} isn't a permutation of {ndim} axes"
+            );
+        }
+        let mut axes = Axes {
+            ndim,
+            shape: [1; MAX_AXES],
+            permutation: [0; MAX_AXES],
+        };
+        axes.shape[..ndim].copy_from_slice(shape);
+        axes.permutation[..ndim].copy_from_slice(permutation);
+        Ok(axes)
+    }
+
+    /// The permutation reversing all `ndim` axes, which is what numpy's
+    /// `transpose()` does without one.
+    pub fn reversed(ndim: usize) -> Vec<usize> {
+        (0..ndim).rev().collect()
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape[..self.ndim]
+    }
+
+    pub fn permutation(&self) -> &[usize] {
+        &self.permutation[..self.ndim]
+    }
+
+    /// The shape of the output.
+    pub fn output_shape(&self) -> Vec<usize> {
+        self.permutation()
+            .iter()
+            .map(|&axis| self.shape[axis])
+            .collect()
+    }
+
+    /// The same permutation of the same bytes with as few axes as it takes:
+    /// axes of length 1 are dropped, and axes that are next to each other and
+    /// in the same order in both the input and the output become one.
+    pub fn collapsed(&self) -> Self {
+        let kept: Vec<usize> = (0..self.ndim)
+            .filter(|&axis| self.shape[axis] > 1)
+            .collect();
+        let mut shape: Vec<usize> = kept.iter().map(|&axis| self.shape[axis]).collect();
+        let mut permutation: Vec<usize> = self
+            .permutation()
+            .iter()
+            .filter_map(|axis| kept.iter().position(|kept| kept == axis))
+            .collect();
+        while let Some(k) =
+            (1..permutation.len()).find(|&k| permutation[k] == permutation[k - 1] + 1)
+        {
+            let axis = permutation[k - 1];
+            shape[axis] *= shape.remove(axis + 1);
+            permutation.remove(k);
+            for later in permutation.iter_mut().filter(|later| **later > axis) {
+                *later -= 1;
+            }
+        }
+        Axes::new(&shape, &permutation).expect("collapsing keeps a permutation valid")
+    }
+
+    /// The strides of each axis in the input, in elements.
+    fn input_strides(&self) -> [u64; MAX_AXES] {
+        let mut strides = [1; MAX_AXES];
+        for axis in (0..self.ndim.saturating_sub(1)).rev() {
+            strides[axis] = strides[axis + 1] * self.shape[axis + 1] as u64;
+        }
+        strides
+    }
+
+    /// Every axis of the input, in input order.
+    pub fn walks(&self) -> Vec<Walk> {
+        let input_strides = self.input_strides();
+        let mut output_strides = [1; MAX_AXES];
+        let mut stride = 1;
+        for &axis in self.permutation().iter().rev() {
+            output_strides[axis] = stride;
+            stride *= self.shape[axis] as u64;
+        }
+        (0..self.ndim)
+            .map(|axis| Walk {
+                len: self.shape[axis],
+                input_stride: input_strides[axis],
+                output_stride: output_strides[axis],
+            })
+            .collect()
+    }
+
+    /// The input element that ends up as output element `element`.
+    pub fn source(&self, element: u64) -> u64 {
+        let input_strides = self.input_strides();
+        let mut rest = element;
+        let mut source = 0;
+        for &axis in self.permutation().iter().rev() {
+            let len = self.shape[axis] as u64;
+            source += rest % len * input_strides[axis];
+            rest /= len;
+        }
+        source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_to_as_few_axes_as_it_takes() {
+        let collapsed = |shape: &[usize], permutation: &[usize]| {
+            let axes = Axes::new(shape, permutation).unwrap().collapsed();
+            (axes.shape().to_vec(), axes.permutation().to_vec())
+        };
+        // the last two axes move together, so it's a matrix transpose
+        assert_eq!(collapsed(&[2, 3, 4], &[2, 0, 1]), (vec![6, 4], vec![1, 0]));
+        assert_eq!(collapsed(&[2, 3, 4], &[1, 2, 0]), (vec![2, 12], vec![1, 0]));
+        assert_eq!(
+            collapsed(&[2, 3, 4], &[0, 2, 1]),
+            (vec![2, 3, 4], vec![0, 2, 1])
+        );
+        assert_eq!(
+            collapsed(&[2, 1, 3, 4, 5], &[0, 1, 4, 2, 3]),
+            (vec![2, 12, 5], vec![0, 2, 1])
+        );
+        assert_eq!(collapsed(&[2, 3, 4], &[0, 1, 2]), (vec![24], vec![0]));
+        assert_eq!(collapsed(&[1, 1], &[1, 0]), (vec![], vec![]));
+
+        assert!(Axes::new(&[2, 3], &[0, 0]).is_err());
+        assert!(Axes::new(&[2, 3], &[0, 2]).is_err());
+        assert!(Axes::new(&[2, 3], &[0]).is_err());
+        assert!(Axes::new(&[2, 0], &[1, 0]).is_err());
+    }
+
+    #[test]
+    fn finds_where_elements_come_from() {
+        let (shape, permutation) = ([2, 3, 4, 5], [2, 0, 3, 1]);
+        let axes = Axes::new(&shape, &permutation).unwrap();
+        assert_eq!(axes.output_shape(), [4, 2, 5, 3]);
+        let mut element = 0;
+        for c in 0..4 {
+            for a in 0..2 {
+                for d in 0..5 {
+                    for b in 0..3 {
+                        let source = ((a * 3 + b) * 4 + c) * 5 + d;
+                        assert_eq!(axes.source(element), source);
+                        let walks = axes.walks();
+                        let from_walks: u64 = [a, b, c, d]
+                            .iter()
+                            .zip(&walks)
+                            .map(|(index, walk)| index * walk.output_stride)
+                            .sum();
+                        assert_eq!(from_walks, element);
+                        element += 1;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use crate::generate::Generator;
+use crate::results::{RunResults, Summary};
+use anyhow::{Result, ensure};
+use std::fs::create_dir_all;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_BASELINE_DIR: &str = "baselines";
+
+/// Relative changes smaller than this are reported as no change, even when
+/// they're statistically significant.
+pub const NOISE_THRESHOLD: f64 = 0.02;
+
+pub fn baseline_path(baseline_dir: &Path, name: &str) -> PathBuf {
+    baseline_dir.join(format!("{name}.json"))
+}
+
+pub fn save_baseline(baseline_dir: &Path, name: &str, results: &RunResults) -> Result<PathBuf> {
+    create_dir_all(baseline_dir)?;
+    let path = baseline_path(baseline_dir, name);
+    results.save(&path)?;
+    Ok(path)
+}
+
+pub fn load_baseline(baseline_dir: &Path, name: &str) -> Result<RunResults> {
+    RunResults::load(&baseline_path(baseline_dir, name))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Improved,
+    Regressed,
+    NoChange,
+    /// fewer than two runs on either side, so there's no variance to test
+    /// with, or a baseline that took no time to change relative to
+    Inconclusive,
+}
+
+/// How the headline time of one strategy moved relative to the baseline.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub strategy: String,
+    pub baseline: Summary,
+    pub current: Summary,
+    /// `(current - baseline) / baseline` of the mean headline time, so
+    /// negative is faster, `None` if the baseline took no time
+    pub change: Option<f64>,
+    pub verdict: Verdict,
+}
+
+/// Compare every strategy that ran in both `baseline` and `current`.
+///
+/// A change is only flagged if Welch's t-test says the means differ at the
+/// 95% level and the change is larger than [`NOISE_THRESHOLD`].
+pub fn compare(baseline: &RunResults, current: &RunResults) -> Result<Vec<Comparison>> {
+    let shape = |results: &RunResults| {
+        (
+            results.rows,
+            results.cols,
+            results.elem_size,
+            results.size,
+            results.permuted,
+        )
+    };
+    ensure!(
+        shape(baseline) == shape(current),
+        "baseline is {} but this run is {}",
+        describe_shape(baseline),
+        describe_shape(current),
+    );
+    let input = |results: &RunResults| (results.generator, results.input.clone());
+    ensure!(
+        input(baseline) == input(current),
+        "baseline input was {} but this run's is {}",
+        describe_input(baseline),
+        describe_input(current),
+    );
+    let comparisons = current
+        .strategies
+        .iter()
+        .filter_map(|current| {
+            let baseline = baseline.strategy(&current.strategy)?.headline_summary();
+            let current_summary = current.headline_summary();
+            // a baseline that took no time can't be changed relative to
+            let change = (baseline.mean > 0.0)
+                .then(|| (current_summary.mean - baseline.mean) / baseline.mean);
+            let verdict = match (change, welch_significant(baseline, current_summary)) {
+                (None, _) | (_, None) => Verdict::Inconclusive,
+                (Some(change), Some(true)) if change.abs() > NOISE_THRESHOLD => {
+                    if change < 0.0 {
+                        Verdict::Improved
+                    } else {
+                        Verdict::Regressed
+                    }
+                }
+                _ => Verdict::NoChange,
+            };
+            Some(Comparison {
+                strategy: current.strategy.clone(),
+                baseline,
+                current: current_summary,
+                change,
+                verdict,
+            })
+        })
+        .collect();
+    Ok(comparisons)
+}
+
+/// The shape of the input of `results`, and how it was permuted, for error
+/// messages.
+fn describe_shape(results: &RunResults) -> String {
+    let elements = format!("{} byte elements", results.elem_size);
+    match results.permuted {
+        Some(axes) => format!(
+            "a {:?} array of {elements} permuted by {:?}",
+            axes.shape(),
+            axes.permutation()
+        ),
+        None => format!("a {}x{} matrix of {elements}", results.rows, results.cols),
+    }
+}
+
+/// What made the input of `results`, for error messages.
+fn describe_input(results: &RunResults) -> String {
+    if let Some(Generator {
+        kind,
+        seed,
+        density,
+    }) = results.generator
+    {
+        return format!(
+            "generated by the {kind:?} generator with seed {seed} and density {density}"
+        );
+    }
+    match &results.input {
+        Some(input) => format!("the file {}", input.display()),
+        None => "a user supplied file".to_string(),
+    }
+}
+
+/// Two-sided Welch's t-test at the 95% level, `None` if either side has too
+/// few samples to estimate its variance.
+fn welch_significant(a: Summary, b: Summary) -> Option<bool> {
+    if a.count < 2 || b.count < 2 {
+        return None;
+    }
+    let var_a = a.std_dev.powi(2) / a.count as f64;
+    let var_b = b.std_dev.powi(2) / b.count as f64;
+    let standard_error = (var_a + var_b).sqrt();
+    if standard_error == 0.0 {
+        return Some(a.mean != b.mean);
+    }
+    let t = (a.mean - b.mean).abs() / standard_error;
+    let degrees_of_freedom = (var_a + var_b).powi(2)
+        / (var_a.powi(2) / (a.count - 1) as f64 + var_b.powi(2) / (b.count - 1) as f64);
+    Some(t > t_critical_95(degrees_of_freedom))
+}
+
+/// Two-sided 95% critical values of Student's t distribution, rounding the
+/// degrees of freedom down to be conservative.
+fn t_critical_95(degrees_of_freedom: f64) -> f64 {
+    const TABLE: [(f64, f64); 14] = [
+        (1.0, 12.706),
+        (2.0, 4.303),
+        (3.0, 3.182),
+        (4.0, 2.776),
+        (5.0, 2.571),
+        (6.0, 2.447),
+        (7.0, 2.365),
+        (8.0, 2.306),
+        (9.0, 2.262),
+        (10.0, 2.228),
+        (15.0, 2.131),
+        (20.0, 2.086),
+        (30.0, 2.042),
+        (60.0, 2.000),
+    ];
+    TABLE
+        .iter()
+        .rev()
+        .find(|(df, _)| *df <= degrees_of_freedom)
+        .map_or(TABLE[0].1, |(_, critical)| *critical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dimensions;
+    use crate::generate::GeneratorKind;
+    use crate::results::StrategyResult;
+    use crate::timing::{Phase, PhaseTimings};
+    use std::time::Duration;
+
+    fn results_with_headlines(millis: &[u64]) -> RunResults {
+        let mut results =
+            RunResults::new(Dimensions::from_log2_size(4), Some(Generator::default()));
+        let runs = millis
+            .iter()
+            .map(|millis| {
+                let mut timings = PhaseTimings::default();
+                timings.record(Phase::Transpose, Duration::from_millis(*millis));
+                timings.record(Phase::Setup, Duration::from_millis(100));
+                timings
+            })
+            .collect();
+        results.strategies.push(StrategyResult {
+            strategy: "buffered_on_disk".to_string(),
+            runs,
+            io: Vec::new(),
+            cancelled: false,
+        });
+        results
+    }
+
+    #[test]
+    fn detects_regressions_and_noise() {
+        let baseline = results_with_headlines(&[10, 11, 9, 10]);
+        let slower = results_with_headlines(&[40, 41, 39, 40]);
+        let comparison = &compare(&baseline, &slower).unwrap()[0];
+        assert_eq!(comparison.verdict, Verdict::Regressed);
+        assert!(comparison.change.unwrap() > 1.0);
+
+        let comparison = &compare(&slower, &baseline).unwrap()[0];
+        assert_eq!(comparison.verdict, Verdict::Improved);
+
+        let noisy = results_with_headlines(&[5, 15, 8, 12]);
+        let comparison = &compare(&baseline, &noisy).unwrap()[0];
+        assert_eq!(comparison.verdict, Verdict::NoChange);
+
+        let single_run = results_with_headlines(&[40]);
+        let comparison = &compare(&baseline, &single_run).unwrap()[0];
+        assert_eq!(comparison.verdict, Verdict::Inconclusive);
+
+        let instant = results_with_headlines(&[0, 0, 0]);
+        let comparison = &compare(&instant, &slower).unwrap()[0];
+        assert_eq!(
+            (comparison.change, comparison.verdict),
+            (None, Verdict::Inconclusive)
+        );
+    }
+
+    #[test]
+    fn only_compares_the_same_inputs() {
+        let baseline = results_with_headlines(&[10, 11]);
+        let mut wider = results_with_headlines(&[10, 11]);
+        wider.elem_size = 2;
+        wider.size *= 2;
+        assert!(compare(&baseline, &wider).is_err());
+
+        // the same bytes, moved around differently
+        let permuted = |permutation: &[usize]| {
+            let mut results = results_with_headlines(&[10, 11]);
+            results.permuted = Dimensions::permuted(&[2, 2, 4], permutation, 1)
+                .unwrap()
+                .permuted;
+            results
+        };
+        let error = compare(&permuted(&[2, 1, 0]), &permuted(&[1, 0, 2])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "baseline is a [2, 2, 4] array of 1 byte elements permuted by [2, 1, 0] but this \
+             run is a [2, 2, 4] array of 1 byte elements permuted by [1, 0, 2]"
+        );
+        assert!(compare(&baseline, &permuted(&[2, 1, 0])).is_err());
+        assert!(compare(&permuted(&[1, 0, 2]), &permuted(&[1, 0, 2])).is_ok());
+
+        let mut supplied = results_with_headlines(&[10, 11]);
+        supplied.generator = None;
+        let mut random = results_with_headlines(&[10, 11]);
+        random.generator = Some(Generator {
+            kind: GeneratorKind::Random,
+            ..Generator::default()
+        });
+        let mut reseeded = results_with_headlines(&[10, 11]);
+        reseeded.generator = Some(Generator {
+            seed: 1,
+            ..Generator::default()
+        });
+        let mut elsewhere = supplied.clone();
+        elsewhere.input = Some(PathBuf::from("/elsewhere.bin"));
+        for other in [&supplied, &random, &reseeded, &elsewhere] {
+            assert!(compare(other, &baseline).is_err());
+        }
+        let error = compare(&reseeded, &baseline).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "baseline input was generated by the Letters generator with seed 1 and density \
+             0.01 but this run's is generated by the Letters generator with seed 0 and density 0.01"
+        );
+        assert!(compare(&elsewhere, &supplied).is_err());
+        assert!(compare(&elsewhere, &elsewhere).is_ok());
+        assert_eq!(compare(&baseline, &baseline).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn baselines_round_trip() {
+        let baseline_dir = std::env::temp_dir().join("matrix_transposer_baseline_test");
+        let results = results_with_headlines(&[1, 2]);
+        save_baseline(&baseline_dir, "round_trip", &results).unwrap();
+        let loaded = load_baseline(&baseline_dir, "round_trip").unwrap();
+        assert_eq!(loaded.strategies[0].runs, results.strategies[0].runs);
+        std::fs::remove_dir_all(&baseline_dir).unwrap();
+    }
+}
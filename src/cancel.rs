@@ -0,0 +1,64 @@
+use anyhow::Result;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A flag strategies check between rows (or other units of work), and stop
+/// with [`Cancelled`] once it's set.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+/// The error a strategy stops with once its run was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl Cancellation {
+    /// A flag that's only set by [`cancel`](Self::cancel).
+    pub fn new() -> Self {
+        Cancellation::default()
+    }
+
+    /// A flag that's set on SIGINT, SIGTERM or SIGQUIT. A second signal,
+    /// e.g. pressing Ctrl-C again while a run is cleaning up, exits straight
+    /// away.
+    pub fn on_signals() -> Result<Self> {
+        let cancellation = Cancellation::new();
+        for &signal in signal_hook::consts::TERM_SIGNALS {
+            // this one has to go first, or it'd see the flag the first
+            // signal set
+            signal_hook::flag::register_conditional_shutdown(signal, 1, cancellation.0.clone())?;
+            signal_hook::flag::register(signal, cancellation.0.clone())?;
+        }
+        Ok(cancellation)
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail with [`Cancelled`] if the flag is set.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Cancelled.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Whether `error` is, or was caused by, a cancellation.
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<Cancelled>())
+}
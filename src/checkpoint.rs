@@ -0,0 +1,239 @@
+use crate::{Dimensions, Strategy};
+use anyhow::{Context, Result, bail, ensure};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Whether a strategy keeps a [`Journal`] of how far it got.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Checkpoints {
+    #[default]
+    Off,
+    /// start from scratch, recording progress as it's made
+    Record,
+    /// pick up where the journal of an earlier run left off, or start from
+    /// scratch if there isn't one
+    Resume,
+}
+
+/// What a journal was recorded for. Resuming is only safe if all of it is
+/// still the same.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalHeader {
+    pub strategy: String,
+    pub dims: Dimensions,
+    /// length of the input file
+    pub input_len: u64,
+}
+
+impl fmt::Display for JournalHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Dimensions {
+            rows,
+            cols,
+            elem_size,
+            ..
+        } = self.dims;
+        write!(
+            f,
+            "{} of a {rows}x{cols} matrix of {elem_size} byte elements in a {} byte input",
+            self.strategy, self.input_len
+        )
+    }
+}
+
+impl JournalHeader {
+    pub fn new(strategy: Strategy, dims: Dimensions, input_path: &Path) -> Result<Self> {
+        Ok(JournalHeader {
+            strategy: strategy.name().to_string(),
+            dims,
+            input_len: input_path.metadata()?.len(),
+        })
+    }
+}
+
+/// An append-only record of how many input rows are durably transposed into
+/// an output.
+///
+/// The first line is the [`JournalHeader`] as JSON, and every line after it
+/// a row count. A line is only appended once the output is synced up to that
+/// row, and is synced itself before the run carries on, so after a crash the
+/// last whole line is a safe place to resume from.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+}
+
+impl Journal {
+    /// The journal of the output at `output_path`.
+    pub fn path_for(output_path: &Path) -> PathBuf {
+        let mut path = output_path.as_os_str().to_owned();
+        path.push(".journal");
+        PathBuf::from(path)
+    }
+
+    /// Start a new journal at `path`, replacing any old one.
+    pub fn create(path: &Path, header: &JournalHeader) -> Result<Self> {
+        let mut file = File::create(path)
+            .with_context(|| format!("couldn't create journal {}", path.display()))?;
+        serde_json::to_writer(&mut file, header)?;
+        file.write_all(b"\n")?;
+        file.sync_data()?;
+        Ok(Journal {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Open the journal at `path` to carry on recording into, with the
+    /// number of rows it says are done. `None` if there's no journal.
+    ///
+    /// Fails if the journal was recorded for a different input, shape or
+    /// strategy, or if the output at `output_path` isn't the size it was
+    /// left at.
+    pub fn resume(
+        path: &Path,
+        header: &JournalHeader,
+        output_path: &Path,
+    ) -> Result<Option<(Self, usize)>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("couldn't read journal {}", path.display()));
+            }
+        };
+        // a line without its newline was cut off by a crash, so it doesn't
+        // count, and goes so the next entry starts on a line of its own
+        let whole_len = contents.rfind('\n').map_or(0, |end| end + 1);
+        if whole_len == 0 {
+            // not even the header made it, so nothing was written after it
+            return Ok(None);
+        }
+        let rows_done = parse(&contents[..whole_len], header)
+            .with_context(|| format!("can't resume from journal {}", path.display()))?;
+        let output_len = output_path.metadata().map(|metadata| metadata.len()).ok();
+        ensure!(
+            output_len == Some(header.dims.size),
+            "can't resume from journal {}: the output {} should be {} bytes, but is {}",
+            path.display(),
+            output_path.display(),
+            header.dims.size,
+            output_len.map_or("missing".to_string(), |len| format!("{len} bytes")),
+        );
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("couldn't open journal {}", path.display()))?;
+        file.set_len(whole_len as u64)?;
+        Ok(Some((
+            Journal {
+                file,
+                path: path.to_path_buf(),
+            },
+            rows_done,
+        )))
+    }
+
+    /// Record that the first `rows_done` input rows are durably transposed.
+    /// The output has to be synced before calling this.
+    pub fn record(&mut self, rows_done: usize) -> Result<()> {
+        writeln!(self.file, "{rows_done}")?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// The output is complete, so there's nothing left to resume.
+    pub fn finish(self) -> Result<()> {
+        std::fs::remove_file(&self.path)
+            .with_context(|| format!("couldn't delete journal {}", self.path.display()))
+    }
+}
+
+/// The rows done according to the whole lines of a journal, checking it was
+/// recorded for `header`.
+fn parse(whole_lines: &str, header: &JournalHeader) -> Result<usize> {
+    let mut lines = whole_lines.lines();
+    let recorded: JournalHeader = match lines.next() {
+        Some(line) => serde_json::from_str(line).context("its header is corrupt")?,
+        None => bail!("it has no header"),
+    };
+    ensure!(
+        recorded == *header,
+        "it was recorded for the {recorded}, but this run is the {header}"
+    );
+    let rows_done = match lines.last() {
+        Some(line) => line
+            .parse()
+            .with_context(|| format!("its last entry {line:?} is corrupt"))?,
+        None => 0,
+    };
+    ensure!(
+        rows_done <= header.dims.rows,
+        "it says {rows_done} rows are done, but there are only {}",
+        header.dims.rows
+    );
+    Ok(rows_done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_from_the_last_whole_entry() {
+        let dir = std::env::temp_dir().join("matrix_transposer_journal");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (output_path, journal_path) = (dir.join("output.md"), dir.join("output.md.journal"));
+        assert_eq!(Journal::path_for(&output_path), journal_path);
+
+        let dims = Dimensions::new(100, 10);
+        let header = JournalHeader {
+            strategy: Strategy::BufferedOnDisk.name().to_string(),
+            dims,
+            input_len: dims.size,
+        };
+        std::fs::write(&output_path, vec![0u8; dims.size as usize]).unwrap();
+        assert!(
+            Journal::resume(&journal_path, &header, &output_path)
+                .unwrap()
+                .is_none()
+        );
+
+        let mut journal = Journal::create(&journal_path, &header).unwrap();
+        journal.record(32).unwrap();
+        journal.record(64).unwrap();
+        // a crash halfway through appending
+        write!(journal.file, "9").unwrap();
+        drop(journal);
+        let (mut journal, rows_done) = Journal::resume(&journal_path, &header, &output_path)
+            .unwrap()
+            .unwrap();
+        assert_eq!(rows_done, 64);
+        journal.record(96).unwrap();
+        drop(journal);
+        let (_, rows_done) = Journal::resume(&journal_path, &header, &output_path)
+            .unwrap()
+            .unwrap();
+        assert_eq!(rows_done, 96);
+
+        let other_input = JournalHeader {
+            input_len: dims.size + 1,
+            ..header.clone()
+        };
+        assert!(Journal::resume(&journal_path, &other_input, &output_path).is_err());
+        std::fs::write(&output_path, b"short").unwrap();
+        assert!(Journal::resume(&journal_path, &header, &output_path).is_err());
+
+        Journal::create(&journal_path, &header)
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert!(!journal_path.exists());
+        std::fs::remove_file(output_path).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Every file a run creates, deleted when this is dropped, so nothing is
+/// left behind when a run returns early or panics.
+///
+/// Files are tracked before they're created, so a file that's only half
+/// written when something goes wrong gets deleted too.
+#[derive(Debug, Default)]
+pub struct WorkFiles {
+    paths: Vec<PathBuf>,
+    keep: bool,
+}
+
+impl WorkFiles {
+    /// Track files that get deleted on drop, or kept around if `keep` is set
+    /// (e.g. for `-k`).
+    pub fn new(keep: bool) -> Self {
+        WorkFiles {
+            paths: Vec::new(),
+            keep,
+        }
+    }
+
+    /// Delete `path` on drop. It doesn't have to exist yet, or at all.
+    pub fn track(&mut self, path: impl Into<PathBuf>) -> &Path {
+        self.paths.push(path.into());
+        self.paths.last().unwrap()
+    }
+
+    /// Delete `path` now instead of on drop, unless these files are kept.
+    pub fn remove(&mut self, path: &Path) -> Result<()> {
+        self.paths.retain(|tracked| tracked != path);
+        if !self.keep {
+            remove_if_exists(path)?;
+        }
+        Ok(())
+    }
+
+    /// Delete every tracked file now, unless these files are kept. Unlike on
+    /// drop, errors are reported, but only after trying every file.
+    pub fn remove_all(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for path in std::mem::take(&mut self.paths) {
+            if !self.keep {
+                result = result.and(remove_if_exists(&path));
+            }
+        }
+        result
+    }
+}
+
+impl Drop for WorkFiles {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+        for path in &self.paths {
+            // there's no one left to report this to, and a file that can't
+            // be deleted shouldn't hide whatever error got us here
+            let _ = remove_if_exists(path);
+        }
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(error) if error.kind() != ErrorKind::NotFound => {
+            Err(error).with_context(|| format!("couldn't delete {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deletes_on_drop_and_panic_unless_kept() {
+        let dir = std::env::temp_dir().join("matrix_transposer_cleanup");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (dropped, panicked, kept) =
+            (dir.join("dropped"), dir.join("panicked"), dir.join("kept"));
+
+        {
+            let mut work_files = WorkFiles::new(false);
+            std::fs::write(work_files.track(&dropped), b"x").unwrap();
+            work_files.track(dir.join("never_created"));
+        }
+        assert!(!dropped.exists());
+
+        let result = std::panic::catch_unwind(|| {
+            let mut work_files = WorkFiles::new(false);
+            std::fs::write(work_files.track(&panicked), b"x").unwrap();
+            panic!("halfway through a run");
+        });
+        assert!(result.is_err());
+        assert!(!panicked.exists());
+
+        {
+            let mut work_files = WorkFiles::new(true);
+            std::fs::write(work_files.track(&kept), b"x").unwrap();
+            work_files.remove_all().unwrap();
+        }
+        assert!(kept.exists());
+        std::fs::remove_file(kept).unwrap();
+    }
+}
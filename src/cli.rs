@@ -0,0 +1,581 @@
+use crate::axes::Axes;
+use crate::baseline::DEFAULT_BASELINE_DIR;
+use crate::checkpoint::Checkpoints;
+use crate::generate::{DEFAULT_DENSITY, Generator, GeneratorKind};
+use crate::npy::NpyHeader;
+use crate::progress::ProgressKind;
+use crate::strategies::Tiling;
+use crate::{Dimensions, Strategy};
+use anyhow::{Result, bail, ensure};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub shape: ShapeArgs,
+
+    /// whether to show little versions of the files after each use
+    #[arg(short)]
+    pub verbose: bool,
+
+    /// check after each algorithm that the result is correct, by recomputing
+    /// every byte from the generated input, or for --input files by
+    /// comparing digests of every row and column
+    #[arg(short)]
+    pub check_work: bool,
+
+    /// number of times to repeat the experiment
+    #[arg(short, default_value_t = 1)]
+    pub times: usize,
+
+    /// run in-memory transpose
+    #[arg(short)]
+    pub in_memory: bool,
+
+    /// run memmap solution
+    #[arg(short)]
+    pub mmap: bool,
+
+    /// run file cat solution
+    #[arg(short)]
+    pub join: bool,
+
+    /// run the transpose entirely on disk
+    #[arg(short)]
+    pub on_disk: bool,
+
+    /// entirely on disk but with a write_buffer to minimize the writes
+    #[arg(short)]
+    pub buff_on_disk: bool,
+
+    /// **toggle** all solutions.
+    /// Doing -a and -b, for example, will run all solutions except the
+    /// buffered one.
+    #[arg(short)]
+    pub all: bool,
+
+    /// pick the method, and its buffer sizes, from the memory and disk space
+    /// available, and say why
+    #[arg(long, conflicts_with_all = [
+        "in_memory", "mmap", "join", "on_disk", "buff_on_disk", "all", "band_rows", "pass_cols",
+    ])]
+    pub auto: bool,
+
+    #[command(flatten)]
+    pub tiling: TilingArgs,
+
+    /// keep files after they've been completed and tested
+    #[arg(short)]
+    pub keep_around: bool,
+
+    /// save the results of this run as a baseline with this name
+    #[arg(long, value_name = "NAME")]
+    pub save_baseline: Option<String>,
+
+    /// compare the results of this run against the baseline with this name
+    #[arg(long, value_name = "NAME")]
+    pub compare_baseline: Option<String>,
+
+    /// directory baselines are saved to and loaded from
+    #[arg(long, default_value = DEFAULT_BASELINE_DIR)]
+    pub baseline_dir: PathBuf,
+
+    /// what to fill the generated input with
+    #[arg(long, value_enum, default_value_t = GeneratorKind::Letters)]
+    pub generator: GeneratorKind,
+
+    /// seed of the random and sparse generators
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// fraction of nonzero elements of the sparse generator
+    #[arg(long, default_value_t = DEFAULT_DENSITY, value_parser = parse_density)]
+    pub density: f64,
+
+    /// write the structured results of this run to this file, e.g. for `report`
+    #[arg(long, value_name = "FILE")]
+    pub results: Option<PathBuf>,
+
+    /// directory the generated input and every output go in
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub workdir: PathBuf,
+
+    /// directory for scratch files, like the join solution's column files,
+    /// the system's temp dir by default
+    #[arg(long, value_name = "DIR")]
+    pub tempdir: Option<PathBuf>,
+
+    /// write every output under a temporary name, sync it and rename it
+    /// into place, so a file at an output path is always complete
+    #[arg(long)]
+    pub atomic: bool,
+
+    /// keep a journal of how far the buffered solution got, so a run that
+    /// was interrupted can be picked up with --resume
+    #[arg(long)]
+    pub checkpoint: bool,
+
+    /// carry on from the journal an interrupted --checkpoint run left, after
+    /// checking it's for the same input; starts over if there isn't one
+    #[arg(long)]
+    pub resume: bool,
+
+    /// how to report progress: a bar, nothing, or one JSON object per line
+    /// on stderr
+    #[arg(long, value_enum, default_value_t = ProgressKind::Bar)]
+    pub progress: ProgressKind,
+
+    /// don't report progress, same as --progress silent
+    #[arg(short, long, conflicts_with = "progress")]
+    pub quiet: bool,
+
+    /// print what the cost model predicted next to every method's timings
+    #[arg(long)]
+    pub estimate: bool,
+
+    /// device numbers as saved by `probe`, for --estimate and --auto, and
+    /// recorded in --results. Without them --estimate uses rough guesses for
+    /// a NVMe SSD
+    #[arg(long, value_name = "FILE")]
+    pub device: Option<PathBuf>,
+}
+
+// The matrix to transpose. Not in a doc comment, which clap would show as
+// the about text of every command this is flattened into.
+#[derive(Args)]
+pub struct ShapeArgs {
+    /// the log2 size of the files to test over
+    #[arg(default_value_t = 20)]
+    pub log2_size: u32,
+
+    /// transpose this file instead of generating one, a .npy file or a raw
+    /// matrix with --cols
+    #[arg(long, value_name = "FILE")]
+    pub input: Option<PathBuf>,
+
+    /// the number of columns of a raw --input matrix
+    #[arg(long, requires = "input")]
+    pub cols: Option<usize>,
+
+    /// bytes per matrix element, a power of two for generated inputs, and
+    /// the dtype's size for .npy inputs
+    #[arg(long, default_value_t = 1, value_name = "BYTES")]
+    pub elem_size: usize,
+
+    /// the lengths of the axes of a row-major N-d array to permute instead
+    /// of a matrix, e.g. 64,32,16, either generated or a raw --input
+    #[arg(
+        long = "shape",
+        value_name = "LENS",
+        value_delimiter = ',',
+        conflicts_with = "cols"
+    )]
+    pub array_shape: Option<Vec<usize>>,
+
+    /// the order the axes go in, e.g. 0,2,1, for a --shape or a .npy
+    /// --input; reversed by default, like numpy's transpose
+    #[arg(long, value_name = "AXES", value_delimiter = ',')]
+    pub permute: Option<Vec<usize>>,
+}
+
+impl ShapeArgs {
+    /// The header of the --input file and where its payload starts, if it's
+    /// a .npy file.
+    pub fn npy(&self) -> Result<Option<NpyInput>> {
+        match &self.input {
+            Some(input) if NpyHeader::is_npy(input) => NpyHeader::of_file(input).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// The --permute permutation of `ndim` axes.
+    pub fn permutation(&self, ndim: usize) -> Vec<usize> {
+        self.permute.clone().unwrap_or_else(|| Axes::reversed(ndim))
+    }
+
+    pub fn dims(&self) -> Result<Dimensions> {
+        if let Some((header, payload_offset)) = self.npy()? {
+            ensure!(
+                self.cols.is_none() && self.elem_size == 1 && self.array_shape.is_none(),
+                "the shape and element size of a .npy input come from its header"
+            );
+            let dims = match (&self.permute, header.shape.len()) {
+                (None, 2) => header.dims(),
+                (_, ndim) => header.permuted_dims(&self.permutation(ndim)),
+            }?;
+            return Ok(dims.with_input_offset(payload_offset));
+        }
+        if let Some(shape) = &self.array_shape {
+            let dims = Dimensions::permuted(shape, &self.permutation(shape.len()), self.elem_size)?;
+            if let Some(input) = &self.input {
+                let len = input.metadata()?.len();
+                ensure!(
+                    len == dims.size,
+                    "{} is {len} bytes, but a {shape:?} array of {} byte elements is {}",
+                    input.display(),
+                    self.elem_size,
+                    dims.size
+                );
+            }
+            return Ok(dims);
+        }
+        ensure!(
+            self.permute.is_none(),
+            "--permute needs a --shape, or a .npy --input"
+        );
+        match (&self.input, self.cols) {
+            (Some(input), Some(cols)) => Dimensions::of_file(input, cols, self.elem_size),
+            (Some(_), None) => bail!("--input needs --cols or --shape unless it's a .npy file"),
+            (None, _) => Dimensions::generated(self.log2_size, self.elem_size),
+        }
+    }
+}
+
+// How the buffered solution tiles the matrix, like `ShapeArgs` not in a doc
+// comment.
+#[derive(Args)]
+pub struct TilingArgs {
+    /// rows the buffered solution collects before writing every column out
+    #[arg(long, value_name = "ROWS", value_parser = parse_positive)]
+    pub band_rows: Option<usize>,
+
+    /// columns the buffered solution transposes per pass over the input,
+    /// all of them by default
+    #[arg(long, value_name = "COLS", value_parser = parse_positive)]
+    pub pass_cols: Option<usize>,
+}
+
+impl TilingArgs {
+    pub fn tiling(&self) -> Tiling {
+        Tiling {
+            band_rows: self.band_rows.unwrap_or(Tiling::default().band_rows),
+            pass_cols: self.pass_cols,
+        }
+    }
+}
+
+/// A delimited text file to transpose.
+#[derive(Args)]
+pub struct TextArgs {
+    pub input: PathBuf,
+
+    /// where to write the transpose
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// the field delimiter, a tab for .tsv files and a comma otherwise
+    #[arg(long)]
+    pub delimiter: Option<char>,
+
+    /// the method that transposes the index
+    #[arg(long, default_value = "buffered_on_disk", value_parser = parse_strategy)]
+    pub method: Strategy,
+
+    /// where the index goes while it's transposed
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    pub workdir: PathBuf,
+
+    #[command(flatten)]
+    pub tiling: TilingArgs,
+
+    /// check that the index was transposed correctly, by comparing digests
+    /// of its rows and columns
+    #[arg(short)]
+    pub check_work: bool,
+
+    /// don't report progress
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// keep the index and its transpose around
+    #[arg(short)]
+    pub keep_around: bool,
+}
+
+/// A Matrix Market file to transpose.
+#[derive(Args)]
+pub struct MtxArgs {
+    pub input: PathBuf,
+
+    /// where to write the transpose
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// MiB of entries to sort in memory at once, before spilling them to a
+    /// sorted run on disk
+    #[arg(long, value_name = "MIB", default_value_t = 256)]
+    pub memory: usize,
+
+    /// where the sorted runs go, the system's temp dir by default
+    #[arg(long, value_name = "DIR")]
+    pub tempdir: Option<PathBuf>,
+
+    /// number of times to repeat the transpose
+    #[arg(short, default_value_t = 1)]
+    pub times: usize,
+
+    /// check that the output is the transpose, by comparing digests of the
+    /// entries and that they're in order
+    #[arg(short)]
+    pub check_work: bool,
+
+    /// don't report progress
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+/// A CSR or CSC matrix to convert to the other format.
+#[derive(Args)]
+pub struct SparseArgs {
+    /// a directory with indptr.npy, indices.npy, data.npy, shape.npy and
+    /// format.npy, like an unzipped scipy .npz file
+    pub input: PathBuf,
+
+    /// the directory to write the converted matrix to
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// entries of each output row (CSR) or column (CSC) collected before
+    /// they're written out together
+    #[arg(long, value_name = "ENTRIES", default_value_t = Tiling::default().band_rows)]
+    pub segment_len: usize,
+
+    /// output rows (CSR) or columns (CSC) converted per pass over the
+    /// input, all of them by default
+    #[arg(long, value_name = "N")]
+    pub pass_len: Option<usize>,
+
+    /// number of times to repeat the conversion
+    #[arg(short, default_value_t = 1)]
+    pub times: usize,
+
+    /// check that the output holds the same entries, by comparing digests of
+    /// them, and that its indices are sorted
+    #[arg(short)]
+    pub check_work: bool,
+
+    /// don't report progress
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+fn parse_strategy(name: &str) -> Result<Strategy, String> {
+    Strategy::from_name(name).ok_or_else(|| {
+        let names: Vec<_> = Strategy::ALL.map(Strategy::name).into();
+        format!("one of {}", names.join(", "))
+    })
+}
+
+fn parse_positive(count: &str) -> Result<usize, String> {
+    match count.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err("a whole number above 0".to_string()),
+    }
+}
+
+fn parse_density(density: &str) -> Result<f64, String> {
+    match density.parse::<f64>() {
+        // NaN is in no range
+        Ok(density) if (0.0..=1.0).contains(&density) => Ok(density),
+        _ => Err("a fraction from 0 to 1".to_string()),
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// build a standalone HTML report from the results of previous runs
+    Report {
+        /// result files written with --results or --save-baseline
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// where to write the report
+        #[arg(short, long, default_value = "report.html")]
+        output: PathBuf,
+    },
+    /// predict how long every method takes, without running any
+    Estimate {
+        #[command(flatten)]
+        shape: ShapeArgs,
+
+        #[command(flatten)]
+        tiling: TilingArgs,
+
+        /// device numbers as saved by `probe`, rough guesses for a NVMe SSD
+        /// otherwise
+        #[arg(long, value_name = "FILE")]
+        device: Option<PathBuf>,
+    },
+    /// measure the storage a directory is on, for --device
+    Probe {
+        /// the log2 size of the scratch file, which should be too big for
+        /// the device's own cache
+        #[arg(default_value_t = 28)]
+        log2_size: u32,
+
+        /// directory to measure, with a scratch file that's deleted
+        /// afterwards
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        workdir: PathBuf,
+
+        /// where to save the measurements
+        #[arg(short, long, default_value = "device.json")]
+        output: PathBuf,
+    },
+    /// transpose a CSV or TSV file, through an index of where its fields are
+    Text(TextArgs),
+    /// transpose a sparse Matrix Market coordinate file by an external sort
+    Mtx(MtxArgs),
+    /// convert a sparse matrix from CSR to CSC or back, with bounded memory
+    ConvertSparse(SparseArgs),
+    /// transpose a .npy file without moving its data, by reversing its
+    /// shape and flipping fortran_order in its header
+    FlipNpy {
+        input: PathBuf,
+
+        /// write the transpose here instead of rewriting the header of INPUT
+        /// in place
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// The header of a .npy input, and where its payload starts.
+pub type NpyInput = (NpyHeader, u64);
+
+/// Every strategy a dense run can use, and whether it's going to.
+pub type Selected = [(bool, Strategy); 5];
+
+impl Cli {
+    /// What --generator, --seed and --density fill a generated input with.
+    pub fn generator(&self) -> Generator {
+        Generator {
+            kind: self.generator,
+            seed: self.seed,
+            density: self.density,
+        }
+    }
+
+    /// Whether --checkpoint or --resume keep a journal.
+    pub fn checkpoints(&self) -> Checkpoints {
+        match (self.checkpoint, self.resume) {
+            (_, true) => Checkpoints::Resume,
+            (true, false) => Checkpoints::Record,
+            (false, false) => Checkpoints::Off,
+        }
+    }
+}
+
+/// Check the arguments of a dense run before anything is written, and work
+/// out what they ask for: the dimensions of the input, the header of a .npy
+/// input and where its payload starts, and the strategies to run, with -a
+/// applied.
+pub fn validate(cli: &Cli) -> Result<(Dimensions, Option<NpyInput>, Selected)> {
+    let dims = cli.shape.dims()?;
+    let npy = cli.shape.npy()?;
+    ensure!(cli.times > 0, "must run a positive amount of runs");
+    let selected = [
+        (cli.in_memory, Strategy::InMemory),
+        (cli.mmap, Strategy::Mmap),
+        (cli.on_disk, Strategy::OnDisk),
+        (cli.buff_on_disk, Strategy::BufferedOnDisk),
+        (cli.join, Strategy::Join),
+    ]
+    // -a toggles every strategy
+    .map(|(enabled, strategy)| (enabled ^ cli.all, strategy));
+    check_selected(&selected, dims, cli.checkpoints())?;
+    Ok((dims, npy, selected))
+}
+
+/// Check that every selected strategy can do what the run asks of it.
+pub fn check_selected(
+    selected: &Selected,
+    dims: Dimensions,
+    checkpoints: Checkpoints,
+) -> Result<()> {
+    if let Some((_, strategy)) = selected.iter().find(|(enabled, strategy)| {
+        *enabled && checkpoints != Checkpoints::Off && !strategy.can_checkpoint()
+    }) {
+        bail!(
+            "--checkpoint and --resume only work with the buffered solution (-b), not {}",
+            strategy.name()
+        );
+    }
+    if let Some((_, strategy)) = selected
+        .iter()
+        .find(|(enabled, strategy)| *enabled && dims.permuted.is_some() && !strategy.can_permute())
+    {
+        bail!(
+            "permuting axes only works with -i, -m and -b, not {}",
+            strategy.name()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["matrix_transposer"].iter().chain(args))
+    }
+
+    #[test]
+    fn density_is_a_fraction() {
+        let density = |value: &str| parse(&["--density", value]).map(|cli| cli.density);
+        assert_eq!(density("0").unwrap(), 0.0);
+        assert_eq!(density("1").unwrap(), 1.0);
+        for bad in ["-0.1", "1.5", "NaN", "inf", "dense"] {
+            assert!(density(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn tiles_have_rows_and_columns() {
+        for flag in ["--band-rows", "--pass-cols"] {
+            assert!(parse(&[flag, "1"]).is_ok());
+            assert!(parse(&[flag, "0"]).is_err(), "{flag}");
+            assert!(parse(&["estimate", flag, "0", "10"]).is_err(), "{flag}");
+        }
+    }
+
+    #[test]
+    fn validates_dense_runs() {
+        let validated = |args: &[&str]| validate(&parse(args).unwrap());
+        let (dims, npy, selected) = validated(&["-a", "-j", "--elem-size", "4", "10"]).unwrap();
+        assert_eq!((dims.size, dims.elem_size, npy), (1024, 4, None));
+        assert_eq!(
+            selected.map(|(enabled, _)| enabled),
+            [true, true, true, true, false]
+        );
+
+        let error = |args: &[&str]| format!("{:#}", validated(args).unwrap_err());
+        assert_eq!(error(&["-t", "0"]), "must run a positive amount of runs");
+        assert_eq!(
+            error(&["--elem-size", "3"]),
+            "the element size has to be a power of two no bigger than the file, not 3"
+        );
+        assert_eq!(
+            error(&["-j", "--checkpoint"]),
+            "--checkpoint and --resume only work with the buffered solution (-b), not join"
+        );
+        assert_eq!(
+            error(&["-o", "--shape", "2,3,4"]),
+            "permuting axes only works with -i, -m and -b, not on_disk"
+        );
+        assert_eq!(
+            error(&["--shape", "4294967296,4294967296,4294967296"]),
+            "a [4294967296, 4294967296, 4294967296] array of 1 byte elements is too big"
+        );
+        assert_eq!(
+            error(&["--permute", "1,0"]),
+            "--permute needs a --shape, or a .npy --input"
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use crate::strategies::Tiling;
+use crate::timing::{Phase, PhaseTimings};
+use crate::{Dimensions, Strategy};
+use anyhow::{Context, Result, ensure};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// Whether an I/O operation pulls data in or pushes it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+/// Whether an I/O operation carries on where the last one of its kind left
+/// off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Sequential,
+    Random,
+}
+
+/// `count` alike I/O operations of `size` bytes each, made during `phase`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoOps {
+    pub phase: Phase,
+    pub direction: Direction,
+    pub access: Access,
+    pub count: u64,
+    pub size: u64,
+}
+
+/// How many random operations of `block_size` bytes a device does a second.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IopsAt {
+    pub block_size: u64,
+    pub iops: f64,
+}
+
+/// What a storage device, and the machine it's in, can do.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    /// sequential read bandwidth, in bytes per second
+    pub seq_read: f64,
+    /// sequential write bandwidth, in bytes per second
+    pub seq_write: f64,
+    /// random read IOPS, by ascending block size
+    pub random_read: Vec<IopsAt>,
+    /// random write IOPS, by ascending block size
+    pub random_write: Vec<IopsAt>,
+    /// seconds it takes to sync a freshly written page
+    #[serde(default = "default_fsync_latency")]
+    pub fsync_latency: f64,
+    /// elements moved to their transposed place in memory per second
+    pub shuffle_rate: f64,
+}
+
+impl Default for DeviceProfile {
+    /// Rough guesses for a NVMe SSD, with writes going through the page
+    /// cache. Calibrating is better.
+    fn default() -> Self {
+        let iops = |table: [(u64, f64); 4]| {
+            table
+                .map(|(block_size, iops)| IopsAt { block_size, iops })
+                .to_vec()
+        };
+        DeviceProfile {
+            seq_read: 2e9,
+            seq_write: 1e9,
+            random_read: iops([(512, 2e5), (4096, 1e5), (65536, 2e4), (1 << 20, 2e3)]),
+            random_write: iops([(512, 1.5e6), (4096, 5e5), (65536, 5e4), (1 << 20, 2e3)]),
+            fsync_latency: default_fsync_latency(),
+            shuffle_rate: 1e8,
+        }
+    }
+}
+
+fn default_fsync_latency() -> f64 {
+    1e-3
+}
+
+impl DeviceProfile {
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("couldn't save device profile to {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read(path)
+            .with_context(|| format!("no device profile at {}", path.display()))?;
+        let profile: DeviceProfile = serde_json::from_slice(&json)
+            .with_context(|| format!("couldn't parse device profile {}", path.display()))?;
+        profile
+            .check()
+            .with_context(|| format!("device profile {} is unusable", path.display()))?;
+        Ok(profile)
+    }
+
+    /// Every rate, latency and block size has to be a number the cost model
+    /// can divide by.
+    fn check(&self) -> Result<()> {
+        let tables = [
+            ("random_read", &self.random_read),
+            ("random_write", &self.random_write),
+        ];
+        for (name, table) in tables {
+            for IopsAt { block_size, iops } in table {
+                ensure!(*block_size > 0, "{name} has a block size of 0");
+                ensure!(
+                    iops.is_finite() && *iops > 0.0,
+                    "{name} has {iops} IOPS at {block_size} bytes, not a positive number"
+                );
+            }
+        }
+        let numbers = [
+            ("seq_read", self.seq_read),
+            ("seq_write", self.seq_write),
+            ("fsync_latency", self.fsync_latency),
+            ("shuffle_rate", self.shuffle_rate),
+        ];
+        for (name, value) in numbers {
+            ensure!(
+                value.is_finite() && value > 0.0,
+                "{name} is {value}, not a positive number"
+            );
+        }
+        Ok(())
+    }
+
+    /// The smallest power of two size, from 512 bytes up to `max`, at which
+    /// random writes reach half the sequential write bandwidth, if any does.
+    pub fn efficient_write_size(&self, max: u64) -> Option<u64> {
+        std::iter::successors(Some(512u64), |size| Some(size * 2))
+            .take_while(|size| *size <= max)
+            .find(|size| {
+                self.random_iops(Direction::Write, *size) * *size as f64 >= self.seq_write / 2.0
+            })
+    }
+
+    /// The sequential bandwidth and random IOPS table for `direction`.
+    fn direction(&self, direction: Direction) -> (f64, &[IopsAt]) {
+        match direction {
+            Direction::Read => (self.seq_read, &self.random_read),
+            Direction::Write => (self.seq_write, &self.random_write),
+        }
+    }
+
+    /// Random operations of `size` bytes per second, see [`iops_at`].
+    pub fn random_iops(&self, direction: Direction, size: u64) -> f64 {
+        let (bandwidth, table) = self.direction(direction);
+        iops_at(table, size, bandwidth)
+    }
+
+    /// How long `ops` take on this device.
+    pub fn time(&self, ops: &IoOps) -> Duration {
+        let (bandwidth, _) = self.direction(ops.direction);
+        let streamed = (ops.count * ops.size) as f64 / bandwidth;
+        let seconds = match ops.access {
+            Access::Sequential => streamed,
+            // no faster than streaming the same bytes
+            Access::Random => {
+                (ops.count as f64 / self.random_iops(ops.direction, ops.size)).max(streamed)
+            }
+        };
+        Duration::from_secs_f64(seconds)
+    }
+}
+
+/// The IOPS at `size` byte operations, interpolated between the calibrated
+/// block sizes on a log-log scale. Smaller operations cost as much as the
+/// smallest calibrated one, and bigger ones are limited by the IOPS of the
+/// biggest, scaled to their size.
+fn iops_at(table: &[IopsAt], size: u64, bandwidth: f64) -> f64 {
+    let (Some(first), Some(last)) = (table.first(), table.last()) else {
+        return bandwidth / size.max(1) as f64;
+    };
+    if size <= first.block_size {
+        return first.iops;
+    }
+    if size >= last.block_size {
+        return last.iops * last.block_size as f64 / size as f64;
+    }
+    let upper = table
+        .iter()
+        .position(|point| point.block_size >= size)
+        .expect("size is below the last block size");
+    let (below, above) = (table[upper - 1], table[upper]);
+    let at = ((size as f64).ln() - (below.block_size as f64).ln())
+        / ((above.block_size as f64).ln() - (below.block_size as f64).ln());
+    (below.iops.ln() + at * (above.iops.ln() - below.iops.ln())).exp()
+}
+
+/// The I/O `strategy` makes to transpose a `dims` matrix, phase by phase.
+/// `tiling` only matters to [`Strategy::BufferedOnDisk`].
+pub fn io_pattern(
+    strategy: Strategy,
+    Dimensions {
+        size,
+        rows,
+        cols,
+        elem_size,
+        ..
+    }: Dimensions,
+    tiling: Tiling,
+) -> Vec<IoOps> {
+    const PAGE: u64 = 4096;
+    /// the default capacity of a `BufWriter`, which the join strategy has
+    /// one of per column
+    const JOIN_BUFFER: u64 = 8192;
+    // an empty matrix has no rows, columns or bands to divide its I/O into
+    if size == 0 {
+        return Vec::new();
+    }
+    let (rows, cols, elem_size) = (rows as u64, cols as u64, elem_size as u64);
+    let row_len = cols * elem_size;
+    let ops = |phase, direction, access, count, size| IoOps {
+        phase,
+        direction,
+        access,
+        count,
+        size,
+    };
+    use Access::*;
+    use Direction::*;
+    match strategy {
+        Strategy::InMemory => vec![
+            ops(Phase::Read, Read, Sequential, 1, size),
+            ops(Phase::Write, Write, Sequential, 1, size),
+        ],
+        // page faults, all inside the transpose loop
+        Strategy::Mmap => vec![
+            ops(
+                Phase::Transpose,
+                Read,
+                Sequential,
+                size.div_ceil(PAGE),
+                PAGE,
+            ),
+            ops(Phase::Transpose, Write, Random, size.div_ceil(PAGE), PAGE),
+        ],
+        Strategy::OnDisk => vec![
+            ops(Phase::Read, Read, Sequential, rows, row_len),
+            ops(Phase::Write, Write, Random, rows * cols, elem_size),
+        ],
+        Strategy::BufferedOnDisk => {
+            let passes = tiling.passes(cols as usize) as u64;
+            let bands = rows.div_ceil(tiling.band_rows as u64);
+            let read = if passes == 1 {
+                ops(Phase::Read, Read, Sequential, rows, row_len)
+            } else {
+                // a slice of every row per pass, skipping the rest
+                ops(Phase::Read, Read, Random, rows * passes, row_len / passes)
+            };
+            vec![
+                read,
+                ops(
+                    Phase::Write,
+                    Write,
+                    Random,
+                    bands * cols,
+                    rows * elem_size / bands,
+                ),
+            ]
+        }
+        Strategy::Join => {
+            let column_len = rows * elem_size;
+            let flushes_per_column = column_len.div_ceil(JOIN_BUFFER);
+            vec![
+                ops(Phase::Read, Read, Sequential, rows, row_len),
+                // the column files' buffers filling up in turn
+                ops(
+                    Phase::Transpose,
+                    Write,
+                    Random,
+                    cols * flushes_per_column,
+                    column_len / flushes_per_column,
+                ),
+                ops(Phase::Write, Read, Sequential, cols, column_len),
+                ops(Phase::Write, Write, Sequential, cols, column_len),
+            ]
+        }
+    }
+}
+
+/// How long `strategy` should take per phase, going by its
+/// [I/O pattern](io_pattern) and `profile`.
+pub fn predict(
+    strategy: Strategy,
+    dims: Dimensions,
+    tiling: Tiling,
+    profile: &DeviceProfile,
+) -> PhaseTimings {
+    let mut timings = PhaseTimings::default();
+    for ops in io_pattern(strategy, dims, tiling) {
+        timings.record(ops.phase, profile.time(&ops));
+    }
+    // every strategy syncs its output once it's done
+    timings.record(Phase::Flush, Duration::from_secs_f64(profile.fsync_latency));
+    // every strategy moves every element once
+    let elements = dims.rows as f64 * dims.cols as f64;
+    timings.record(
+        Phase::Transpose,
+        Duration::from_secs_f64(elements / profile.shuffle_rate),
+    );
+    timings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_iops_between_block_sizes() {
+        let profile = DeviceProfile::default();
+        let table = &profile.random_write;
+        let bandwidth = profile.seq_write;
+        assert_eq!(iops_at(table, 1, bandwidth), 1.5e6);
+        assert!((iops_at(table, 4096, bandwidth) - 5e5).abs() < 1e-6);
+        let between = iops_at(table, 16384, bandwidth);
+        assert!(5e4 < between && between < 5e5);
+        // bigger than calibrated is bandwidth bound
+        assert_eq!(iops_at(table, 1 << 21, bandwidth), 1e3);
+        assert_eq!(iops_at(&[], 1000, 1e6), 1e3);
+    }
+
+    #[test]
+    fn loads_only_usable_profiles() {
+        let path = std::env::temp_dir().join("matrix_transposer_device_profile.json");
+        DeviceProfile::default().save(&path).unwrap();
+        assert_eq!(
+            DeviceProfile::load(&path).unwrap(),
+            DeviceProfile::default()
+        );
+
+        let unusable = [
+            DeviceProfile {
+                fsync_latency: 0.0,
+                ..DeviceProfile::default()
+            },
+            DeviceProfile {
+                shuffle_rate: -1e8,
+                ..DeviceProfile::default()
+            },
+            DeviceProfile {
+                random_write: vec![IopsAt {
+                    block_size: 0,
+                    iops: 1e3,
+                }],
+                ..DeviceProfile::default()
+            },
+        ];
+        for profile in unusable {
+            profile.save(&path).unwrap();
+            assert!(DeviceProfile::load(&path).is_err(), "{profile:?}");
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn finds_where_random_writes_catch_up() {
+        let mut profile = DeviceProfile::default();
+        assert_eq!(profile.efficient_write_size(1 << 20), Some(512));
+        profile.random_write = vec![IopsAt {
+            block_size: 4096,
+            iops: 1e3,
+        }];
+        assert_eq!(profile.efficient_write_size(1 << 20), None);
+        profile.random_write.push(IopsAt {
+            block_size: 1 << 20,
+            iops: 1e3,
+        });
+        assert_eq!(profile.efficient_write_size(1 << 20), Some(1 << 19));
+        assert_eq!(profile.efficient_write_size(1 << 18), None);
+    }
+
+    #[test]
+    fn patterns_move_every_byte() {
+        let dims = Dimensions::new(1000, 300).with_elem_size(4);
+        let tilings = [
+            Tiling::default(),
+            Tiling {
+                band_rows: 7,
+                pass_cols: Some(64),
+            },
+        ];
+        for strategy in Strategy::ALL {
+            for tiling in tilings {
+                let bytes = |direction| -> u64 {
+                    io_pattern(strategy, dims, tiling)
+                        .iter()
+                        .filter(|ops| ops.direction == direction)
+                        .map(|ops| ops.count * ops.size)
+                        .sum()
+                };
+                // the join strategy writes and reads it all twice, and
+                // splitting the size up into ops rounds it a bit
+                let expected = dims.size
+                    * match strategy {
+                        Strategy::Join => 2,
+                        _ => 1,
+                    };
+                for direction in [Direction::Read, Direction::Write] {
+                    let moved = bytes(direction);
+                    assert!(
+                        moved.abs_diff(expected) * 10 <= expected,
+                        "{} {direction:?} {moved} of {expected}",
+                        strategy.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_matrices_take_no_io() {
+        let profile = DeviceProfile::default();
+        for dims in [
+            Dimensions::new(0, 4),
+            Dimensions::new(0, 4).with_elem_size(8),
+        ] {
+            for strategy in Strategy::ALL {
+                assert!(io_pattern(strategy, dims, Tiling::default()).is_empty());
+                let predicted = predict(strategy, dims, Tiling::default(), &profile);
+                assert!(predicted.get(Phase::Write).is_zero(), "{}", strategy.name());
+            }
+        }
+    }
+
+    #[test]
+    fn predicts_io_on_top_of_syncing_and_shuffling() {
+        let dims = Dimensions::new(1000, 300).with_elem_size(4);
+        let profile = DeviceProfile::default();
+        let fsync = Duration::from_secs_f64(profile.fsync_latency);
+        let shuffle = Duration::from_secs_f64(300_000.0 / profile.shuffle_rate);
+        for strategy in Strategy::ALL {
+            let predicted = predict(strategy, dims, Tiling::default(), &profile);
+            let name = strategy.name();
+            assert_eq!(predicted.get(Phase::Flush), fsync, "{name}");
+            // the mmap and join strategies do some of their I/O while transposing
+            assert!(predicted.get(Phase::Transpose) >= shuffle, "{name}");
+            assert!(predicted.headline() > fsync + shuffle, "{name}");
+            assert!(predicted.headline() < Duration::from_secs(1), "{name}");
+        }
+    }
+
+    #[test]
+    fn bigger_writes_are_predicted_faster() {
+        let dims = Dimensions::from_log2_size(30);
+        let profile = DeviceProfile::default();
+        let on_disk = predict(Strategy::OnDisk, dims, Tiling::default(), &profile);
+        let buffered = predict(Strategy::BufferedOnDisk, dims, Tiling::default(), &profile);
+        assert!(buffered.headline() < on_disk.headline());
+    }
+}
@@ -0,0 +1,387 @@
+use crate::Dimensions;
+use crate::cancel::Cancellation;
+use crate::progress::Progress;
+use anyhow::{Context, Result, bail, ensure};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+/// Bytes per index entry: where a field starts in the input and how long it
+/// is, as little endian `u64`s.
+pub const ENTRY_SIZE: usize = 16;
+
+const CHUNK_SIZE: usize = 2usize.pow(20);
+
+/// How the fields of a line are separated and quoted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+}
+
+impl Dialect {
+    pub const CSV: Dialect = Dialect {
+        delimiter: b',',
+        quote: b'"',
+    };
+    pub const TSV: Dialect = Dialect {
+        delimiter: b'\t',
+        quote: b'"',
+    };
+
+    /// TSV for `.tsv` and `.tab` files, CSV for anything else.
+    pub fn of_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("tsv" | "tab") => Dialect::TSV,
+            _ => Dialect::CSV,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// at the start of a field
+    Start,
+    Unquoted,
+    Quoted,
+    /// just after a quote in a quoted field, which either closes the field
+    /// or escapes another quote
+    QuoteInQuoted,
+    /// after the closing quote and a carriage return
+    Closed,
+}
+
+/// Turns delimited text into index entries, a chunk at a time.
+///
+/// Fields are indexed as they are in the input, quotes and all, so copying
+/// them somewhere else with the same dialect keeps them intact.
+struct Indexer<W> {
+    dialect: Dialect,
+    entries: W,
+    state: State,
+    prev: u8,
+    /// offset of the next byte fed
+    offset: u64,
+    /// line of the next byte fed, from 1
+    line: u64,
+    field_start: u64,
+    /// where the closing quote of a quoted field ended
+    field_end: u64,
+    /// the line the open quoted field started on
+    quote_line: u64,
+    /// the line the current row started on
+    row_line: u64,
+    /// of the current row so far
+    fields: usize,
+    /// of every row, going by the first
+    cols: Option<usize>,
+    rows: usize,
+}
+
+impl<W: Write> Indexer<W> {
+    fn new(dialect: Dialect, entries: W) -> Self {
+        Indexer {
+            dialect,
+            entries,
+            state: State::Start,
+            prev: 0,
+            offset: 0,
+            line: 1,
+            field_start: 0,
+            field_end: 0,
+            quote_line: 1,
+            row_line: 1,
+            fields: 0,
+            cols: None,
+            rows: 0,
+        }
+    }
+
+    fn feed(&mut self, bytes: &[u8]) -> Result<()> {
+        let Dialect { delimiter, quote } = self.dialect;
+        for &byte in bytes {
+            let ends_field = byte == delimiter || byte == b'\n';
+            match self.state {
+                State::Start | State::Unquoted if ends_field => {
+                    // a carriage return before the newline isn't part of the field
+                    let end =
+                        if byte == b'\n' && self.state == State::Unquoted && self.prev == b'\r' {
+                            self.offset - 1
+                        } else {
+                            self.offset
+                        };
+                    self.end_field(end, byte == b'\n')?;
+                }
+                State::Start if byte == quote => {
+                    self.state = State::Quoted;
+                    self.quote_line = self.line;
+                }
+                State::Start | State::Unquoted => self.state = State::Unquoted,
+                State::Quoted => {
+                    if byte == quote {
+                        self.state = State::QuoteInQuoted;
+                    }
+                }
+                State::QuoteInQuoted if byte == quote => self.state = State::Quoted,
+                State::QuoteInQuoted | State::Closed => {
+                    if self.state == State::QuoteInQuoted {
+                        self.field_end = self.offset;
+                    }
+                    if ends_field {
+                        self.end_field(self.field_end, byte == b'\n')?;
+                    } else if byte == b'\r' {
+                        self.state = State::Closed;
+                    } else {
+                        bail!(
+                            "line {}: {:?} after the closing quote of a field, where only a \
+                             delimiter or the end of the line can be",
+                            self.line,
+                            byte as char
+                        );
+                    }
+                }
+            }
+            if byte == b'\n' {
+                self.line += 1;
+            }
+            self.prev = byte;
+            self.offset += 1;
+        }
+        Ok(())
+    }
+
+    /// Index the field that ends at `end`, and the row if that's the end of
+    /// the line too.
+    fn end_field(&mut self, end: u64, end_of_row: bool) -> Result<()> {
+        self.entries.write_all(&self.field_start.to_le_bytes())?;
+        self.entries
+            .write_all(&(end - self.field_start).to_le_bytes())?;
+        self.fields += 1;
+        self.field_start = self.offset + 1;
+        self.state = State::Start;
+        if end_of_row {
+            match self.cols {
+                None => self.cols = Some(self.fields),
+                Some(cols) => ensure!(
+                    self.fields == cols,
+                    "line {}: row {} has {} fields, but the first row has {cols}",
+                    self.row_line,
+                    self.rows + 1,
+                    self.fields
+                ),
+            }
+            self.rows += 1;
+            self.fields = 0;
+            self.row_line = self.line + 1;
+        }
+        Ok(())
+    }
+
+    /// The dimensions of the index, once the last line has been fed, which
+    /// doesn't need a newline at its end.
+    fn finish(mut self) -> Result<(Dimensions, W)> {
+        match self.state {
+            State::Quoted => bail!("line {}: quoted field is never closed", self.quote_line),
+            // the last line ended with a newline
+            State::Start if self.fields == 0 => {}
+            State::Start | State::Unquoted => {
+                let end = if self.state == State::Unquoted && self.prev == b'\r' {
+                    self.offset - 1
+                } else {
+                    self.offset
+                };
+                self.end_field(end, true)?;
+            }
+            State::QuoteInQuoted => self.end_field(self.offset, true)?,
+            State::Closed => self.end_field(self.field_end, true)?,
+        }
+        let cols = self.cols.context("there are no rows")?;
+        let dims = Dimensions::new(self.rows, cols).with_elem_size(ENTRY_SIZE);
+        Ok((dims, self.entries))
+    }
+}
+
+/// Write an index of where every field of the delimited file at `input` is
+/// to `index_path`, a matrix of [`ENTRY_SIZE`] byte elements with a row per
+/// line, and return its dimensions.
+///
+/// Quoted fields can hold delimiters, newlines and doubled quotes. Every
+/// row has to have as many fields as the first, and errors say on which
+/// line they aren't.
+pub fn index(
+    input: &Path,
+    dialect: Dialect,
+    index_path: &Path,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<Dimensions> {
+    let mut file =
+        File::open(input).with_context(|| format!("couldn't open {}", input.display()))?;
+    let entries = File::create(index_path)
+        .with_context(|| format!("couldn't create {}", index_path.display()))?;
+    let mut indexer = Indexer::new(dialect, BufWriter::with_capacity(CHUNK_SIZE, entries));
+    let mut chunk = vec![0; CHUNK_SIZE];
+    progress.start("indexing", 0, file.metadata()?.len());
+    loop {
+        cancel.check()?;
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        indexer
+            .feed(&chunk[..read])
+            .with_context(|| format!("can't index {}", input.display()))?;
+        progress.advance(0, read as u64);
+    }
+    progress.finish();
+    let (dims, entries) = indexer
+        .finish()
+        .with_context(|| format!("can't index {}", input.display()))?;
+    entries.into_inner()?;
+    Ok(dims)
+}
+
+/// Write the delimited file at `input` transposed to `output_path`, going
+/// by the `transposed_index` of its `dims` index, and return the output
+/// unsynced.
+///
+/// Fields are copied as they are, so the output uses the same `dialect` as
+/// the input and ends every line with a newline.
+pub fn reassemble(
+    input: &Path,
+    dims: Dimensions,
+    transposed_index: &mut File,
+    dialect: Dialect,
+    output_path: &Path,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<File> {
+    let input = File::open(input).with_context(|| format!("couldn't open {}", input.display()))?;
+    transposed_index.seek(SeekFrom::Start(0))?;
+    let mut entries = BufReader::with_capacity(CHUNK_SIZE, transposed_index);
+    let output = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(output_path)
+        .with_context(|| format!("couldn't create {}", output_path.display()))?;
+    let mut output = BufWriter::with_capacity(CHUNK_SIZE, output);
+    let mut entry = [0; ENTRY_SIZE];
+    let mut field = Vec::new();
+    progress.start("reassembling", dims.cols as u64, input.metadata()?.len());
+    // the transposed index has a row per column of the input
+    for _ in 0..dims.cols {
+        cancel.check()?;
+        let mut line_len = 0;
+        for index in 0..dims.rows {
+            entries.read_exact(&mut entry)?;
+            let (offset, len) = entry.split_at(ENTRY_SIZE / 2);
+            let offset = u64::from_le_bytes(offset.try_into()?);
+            field.resize(u64::from_le_bytes(len.try_into()?) as usize, 0);
+            input.read_exact_at(&mut field, offset)?;
+            if index > 0 {
+                output.write_all(&[dialect.delimiter])?;
+            }
+            output.write_all(&field)?;
+            line_len += field.len() as u64 + 1;
+        }
+        output.write_all(b"\n")?;
+        progress.advance(1, line_len);
+    }
+    progress.finish();
+    Ok(output.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Strategy;
+    use crate::progress::Silent;
+    use crate::workspace::Workspace;
+
+    /// The fields of every row of `text`, as indexed.
+    fn fields(text: &str, dialect: Dialect) -> Result<Vec<Vec<&str>>> {
+        let mut indexer = Indexer::new(dialect, Vec::new());
+        // in two chunks, so state carries over between them
+        let (first, second) = text.as_bytes().split_at(text.len() / 2);
+        indexer.feed(first)?;
+        indexer.feed(second)?;
+        let (dims, entries) = indexer.finish()?;
+        let fields: Vec<&str> = entries
+            .chunks(ENTRY_SIZE)
+            .map(|entry| {
+                let offset = u64::from_le_bytes(entry[..8].try_into().unwrap()) as usize;
+                let len = u64::from_le_bytes(entry[8..].try_into().unwrap()) as usize;
+                &text[offset..offset + len]
+            })
+            .collect();
+        Ok(fields.chunks(dims.cols).map(<[_]>::to_vec).collect())
+    }
+
+    #[test]
+    fn indexes_quoted_fields_and_line_endings() {
+        assert_eq!(
+            fields("a,\"b,\"\"c\"\"\"\r\n,\"multi\nline\"\r\nd,e", Dialect::CSV).unwrap(),
+            [
+                vec!["a", "\"b,\"\"c\"\"\""],
+                vec!["", "\"multi\nline\""],
+                vec!["d", "e"]
+            ]
+        );
+        assert_eq!(
+            fields("a\tb,c\n1\t2\n", Dialect::TSV).unwrap(),
+            [vec!["a", "b,c"], vec!["1", "2"]]
+        );
+        assert_eq!(fields("x,\n", Dialect::CSV).unwrap(), [vec!["x", ""]]);
+    }
+
+    #[test]
+    fn errors_say_which_line() {
+        let error = |text| format!("{:#}", fields(text, Dialect::CSV).unwrap_err());
+        assert_eq!(
+            error("a,b\n\"c\nd\",e\nf\n"),
+            "line 4: row 3 has 1 fields, but the first row has 2"
+        );
+        assert_eq!(
+            error("a,b\nc,\"d\n"),
+            "line 2: quoted field is never closed"
+        );
+        assert!(error("a,\"b\"c\n").starts_with("line 1: 'c' after the closing quote"));
+        assert_eq!(error(""), "there are no rows");
+    }
+
+    #[test]
+    fn transposes_through_the_index() {
+        let workspace = Workspace::new(std::env::temp_dir().join("matrix_transposer_text"), None);
+        workspace.create().unwrap();
+        let input = workspace.dir.join("input.csv");
+        std::fs::write(&input, "a,\"b\nb\",c\r\n1,2,\"3,\"\"3\"\"\"\n").unwrap();
+        let index_path = workspace.dir.join("index.md");
+        let (silent, cancel) = (&Silent, &Cancellation::new());
+        let dims = index(&input, Dialect::CSV, &index_path, silent, cancel).unwrap();
+        assert_eq!((dims.rows, dims.cols), (2, 3));
+
+        let strategy = Strategy::BufferedOnDisk;
+        let (mut transposed, _) = strategy
+            .run(dims, &index_path, &workspace, silent, cancel)
+            .unwrap();
+        let output = workspace.dir.join("output.csv");
+        reassemble(
+            &input,
+            dims,
+            &mut transposed,
+            Dialect::CSV,
+            &output,
+            silent,
+            cancel,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "a,1\n\"b\nb\",2\nc,\"3,\"\"3\"\"\"\n"
+        );
+        std::fs::remove_dir_all(&workspace.dir).unwrap();
+    }
+}
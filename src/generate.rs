@@ -0,0 +1,264 @@
+use crate::Dimensions;
+use crate::progress::Progress;
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+/// The pattern the [letters](GeneratorKind::Letters) generator repeats over
+/// the whole input.
+const LETTERS: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!@#$^&*()-+[]";
+
+pub const DEFAULT_DENSITY: f64 = 0.01;
+
+/// How much of the input is generated before it's written out.
+const WRITE_CHUNK_SIZE: usize = 2usize.pow(22);
+
+/// How much of a write chunk one rayon task fills.
+const FILL_CHUNK_SIZE: usize = 2usize.pow(14);
+
+/// The byte at `offset` of an input written by the
+/// [letters](GeneratorKind::Letters) generator.
+pub fn pattern_byte(offset: u64) -> u8 {
+    LETTERS[(offset % LETTERS.len() as u64) as usize]
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum GeneratorKind {
+    /// a 75 character pattern repeated over the whole input, regardless of
+    /// where rows start
+    #[default]
+    Letters,
+    /// every element holds its own row and column (as many bytes of them as
+    /// fit, or a hash of both in one byte elements), so any misplaced
+    /// element is visible
+    Index,
+    /// seeded pseudo-random bytes
+    Random,
+    /// nothing but zeros
+    Zeros,
+    /// mostly zeros, with seeded pseudo-random nonzero elements
+    Sparse,
+}
+
+/// How to fill a generated input.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Generator {
+    pub kind: GeneratorKind,
+    /// seed of the random and sparse generators
+    pub seed: u64,
+    /// the fraction of nonzero elements of the sparse generator
+    pub density: f64,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator {
+            kind: GeneratorKind::default(),
+            seed: 0,
+            density: DEFAULT_DENSITY,
+        }
+    }
+}
+
+impl Generator {
+    /// A function giving the byte at any offset of the input, so an input
+    /// can be generated (or checked, see
+    /// [`verify_with_oracle`](crate::verify::verify_with_oracle)) in any
+    /// order without storing it.
+    pub fn oracle(self, dims: Dimensions) -> Box<dyn Fn(u64) -> u8 + Send + Sync> {
+        let elem_size = dims.elem_size as u64;
+        match self.kind {
+            GeneratorKind::Letters => Box::new(pattern_byte),
+            GeneratorKind::Index => Box::new(move |offset| index_byte(dims, offset)),
+            GeneratorKind::Zeros => Box::new(|_| 0),
+            GeneratorKind::Random => Box::new(move |offset| random_byte(self.seed, offset)),
+            GeneratorKind::Sparse => {
+                // which elements are nonzero comes from a different stream
+                // than their bytes, so the two aren't correlated
+                let nonzero_seed = mix(self.seed);
+                Box::new(move |offset| {
+                    let (element, byte) = (offset / elem_size, offset % elem_size);
+                    if to_unit(hash(nonzero_seed, element)) >= self.density {
+                        0
+                    } else if byte == 0 {
+                        // make sure nonzero elements really are
+                        random_byte(self.seed, offset) | 1
+                    } else {
+                        random_byte(self.seed, offset)
+                    }
+                })
+            }
+        }
+    }
+
+    /// Write a whole `dims` input, a chunk at a time, filling each chunk in
+    /// parallel.
+    pub fn write(
+        self,
+        dims: Dimensions,
+        writer: &mut impl Write,
+        progress: &dyn Progress,
+    ) -> io::Result<()> {
+        let byte_at = self.oracle(dims);
+        let row_len = (dims.cols * dims.elem_size) as u64;
+        let mut buf = vec![0u8; WRITE_CHUNK_SIZE];
+        let mut offset = 0u64;
+        progress.start("generate", dims.rows as u64, dims.size);
+        while offset < dims.size {
+            let len = (dims.size - offset).min(WRITE_CHUNK_SIZE as u64) as usize;
+            buf[..len]
+                .par_chunks_mut(FILL_CHUNK_SIZE)
+                .enumerate()
+                .for_each(|(index, chunk)| {
+                    let chunk_start = offset + (index * FILL_CHUNK_SIZE) as u64;
+                    for (byte_index, byte) in chunk.iter_mut().enumerate() {
+                        *byte = byte_at(chunk_start + byte_index as u64);
+                    }
+                });
+            writer.write_all(&buf[..len])?;
+            let rows_done = (offset + len as u64) / row_len - offset / row_len;
+            progress.advance(rows_done, len as u64);
+            offset += len as u64;
+        }
+        progress.finish();
+        Ok(())
+    }
+}
+
+/// The byte at `offset` of an [index](GeneratorKind::Index) input: the first
+/// half of every element is its row and the second half its column, both
+/// little endian and truncated to fit. One byte elements have no room for
+/// both, so they hold the low byte of a hash of the two.
+fn index_byte(
+    Dimensions {
+        cols, elem_size, ..
+    }: Dimensions,
+    offset: u64,
+) -> u8 {
+    let (element, byte) = (offset / elem_size as u64, offset % elem_size as u64);
+    let (row, col) = (element / cols as u64, element % cols as u64);
+    if elem_size == 1 {
+        return hash(row, col) as u8;
+    }
+    let row_bytes = elem_size.div_ceil(2) as u64;
+    let (value, byte) = if byte < row_bytes {
+        (row, byte)
+    } else {
+        (col, byte - row_bytes)
+    };
+    value.checked_shr(8 * byte as u32).unwrap_or(0) as u8
+}
+
+/// The byte at `offset` of a [random](GeneratorKind::Random) input; every
+/// 8 bytes are one [`hash`].
+fn random_byte(seed: u64, offset: u64) -> u8 {
+    hash(seed, offset / 8).to_le_bytes()[(offset % 8) as usize]
+}
+
+/// The `index`th output of a SplitMix64 generator seeded with `seed`, which
+/// doesn't depend on any of the outputs before it, see
+/// <https://prng.di.unimi.it/splitmix64.c>.
+pub(crate) fn hash(seed: u64, index: u64) -> u64 {
+    mix(seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15)))
+}
+
+/// The SplitMix64 output function.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Map a hash uniformly onto `[0, 1)`.
+fn to_unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Silent;
+
+    fn generate(generator: Generator, dims: Dimensions) -> Vec<u8> {
+        let mut input = Vec::new();
+        generator.write(dims, &mut input, &Silent).unwrap();
+        assert_eq!(input.len() as u64, dims.size);
+        input
+    }
+
+    #[test]
+    fn oracles_match_what_was_written() {
+        let dims = Dimensions::new(37, 11).with_elem_size(3);
+        for kind in GeneratorKind::value_variants().iter().copied() {
+            let generator = Generator {
+                kind,
+                ..Generator::default()
+            };
+            let input = generate(generator, dims);
+            let oracle = generator.oracle(dims);
+            assert!(
+                input
+                    .iter()
+                    .enumerate()
+                    .all(|(offset, byte)| oracle(offset as u64) == *byte)
+            );
+        }
+    }
+
+    #[test]
+    fn index_elements_hold_their_position() {
+        let dims = Dimensions::new(300, 7).with_elem_size(4);
+        let generator = Generator {
+            kind: GeneratorKind::Index,
+            ..Generator::default()
+        };
+        let input = generate(generator, dims);
+        let element = |row: usize, col: usize| {
+            let offset = (row * dims.cols + col) * dims.elem_size;
+            &input[offset..offset + dims.elem_size]
+        };
+        assert_eq!(element(0, 0), [0, 0, 0, 0]);
+        assert_eq!(
+            element(299, 6),
+            [299u16.to_le_bytes(), 6u16.to_le_bytes()].concat()
+        );
+
+        // one byte elements can't hold the row alone, or every row would be
+        // the same all the way along
+        let dims = Dimensions::new(16, 16);
+        let input = generate(generator, dims);
+        for row in input.chunks_exact(dims.cols) {
+            assert!(row.iter().any(|byte| *byte != row[0]), "{row:?}");
+        }
+    }
+
+    #[test]
+    fn seeded_generators_are_reproducible() {
+        let dims = Dimensions::new(64, 64).with_elem_size(2);
+        let random = |seed| Generator {
+            kind: GeneratorKind::Random,
+            seed,
+            ..Generator::default()
+        };
+        assert_eq!(generate(random(1), dims), generate(random(1), dims));
+        assert_ne!(generate(random(1), dims), generate(random(2), dims));
+
+        let sparse = Generator {
+            kind: GeneratorKind::Sparse,
+            density: 0.1,
+            ..Generator::default()
+        };
+        let input = generate(sparse, dims);
+        let nonzero = input
+            .chunks_exact(2)
+            .filter(|element| element[0] != 0)
+            .count();
+        assert!(
+            (200..600).contains(&nonzero),
+            "{nonzero} of 4096 elements are nonzero"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+/// The I/O counters the kernel keeps for this process in `/proc/self/io`.
+///
+/// These cover every thread of the process (so the rayon pools too), but
+/// not page faults, which is how the mmap solution does all of its I/O.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoCounters {
+    /// `read`-like syscalls (`read`, `pread`, `readv`, ...)
+    pub read_syscalls: u64,
+    /// `write`-like syscalls (`write`, `pwrite`, `writev`, ...)
+    pub write_syscalls: u64,
+    /// bytes passed through those reads, whether or not they hit the disk
+    pub read_chars: u64,
+    /// bytes passed through those writes, whether or not they hit the disk
+    pub written_chars: u64,
+}
+
+impl IoCounters {
+    /// The current counters, or `None` where `/proc/self/io` isn't available.
+    pub fn of_this_process() -> Option<Self> {
+        let io = std::fs::read_to_string("/proc/self/io").ok()?;
+        let mut counters = IoCounters::default();
+        for line in io.lines() {
+            let (key, value) = line.split_once(':')?;
+            let value = value.trim().parse().ok()?;
+            match key {
+                "syscr" => counters.read_syscalls = value,
+                "syscw" => counters.write_syscalls = value,
+                "rchar" => counters.read_chars = value,
+                "wchar" => counters.written_chars = value,
+                _ => {}
+            }
+        }
+        Some(counters)
+    }
+
+    /// What happened between `earlier` and `self`.
+    pub fn since(self, earlier: IoCounters) -> IoCounters {
+        IoCounters {
+            read_syscalls: self.read_syscalls.saturating_sub(earlier.read_syscalls),
+            write_syscalls: self.write_syscalls.saturating_sub(earlier.write_syscalls),
+            read_chars: self.read_chars.saturating_sub(earlier.read_chars),
+            written_chars: self.written_chars.saturating_sub(earlier.written_chars),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::{Context, Result, ensure};
+use axes::Axes;
+use generate::Generator;
+use progress::Progress;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub mod axes;
+pub mod baseline;
+pub mod cancel;
+pub mod checkpoint;
+pub mod cleanup;
+pub mod cli;
+pub mod cost;
+pub mod delimited;
+pub mod generate;
+pub mod io_counters;
+pub mod mtx;
+pub mod npy;
+pub mod permute;
+pub mod planner;
+pub mod probe;
+pub mod progress;
+pub mod report;
+pub mod results;
+pub mod sparse;
+pub mod strategies;
+pub mod timing;
+pub mod verify;
+pub mod workspace;
+
+pub use generate::pattern_byte;
+pub use strategies::Strategy;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dimensions {
+    /// in bytes, i.e. `rows * cols * elem_size`
+    pub size: u64,
+    pub rows: usize,
+    pub cols: usize,
+    /// bytes per element, which are moved around as a unit
+    pub elem_size: usize,
+    /// the axes of an N-d array to permute instead of transposing a matrix,
+    /// whose `rows` are then all but its last axis and `cols` the last one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permuted: Option<Axes>,
+    /// where the matrix starts in the input file, e.g. after a `.npy` header
+    #[serde(default)]
+    pub input_offset: u64,
+}
+
+impl Dimensions {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Dimensions {
+            size: rows as u64 * cols as u64,
+            rows,
+            cols,
+            elem_size: 1,
+            permuted: None,
+            input_offset: 0,
+        }
+    }
+
+    /// The same matrix, but starting `input_offset` bytes into the input.
+    pub fn with_input_offset(self, input_offset: u64) -> Self {
+        Dimensions {
+            input_offset,
+            ..self
+        }
+    }
+
+    /// Open the input at `path`, positioned where the matrix starts.
+    pub fn open_input(&self, path: &Path) -> std::io::Result<File> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(self.input_offset))?;
+        Ok(file)
+    }
+
+    /// The dimensions of permuting the axes of a row-major array of `shape`
+    /// and `elem_size` byte elements by `permutation`, see [`Axes`].
+    ///
+    /// The axes are [collapsed](Axes::collapsed) first, so permutations that
+    /// come down to a matrix transpose, like `(2, 0, 1)`, are plain matrices
+    /// every strategy can transpose.
+    pub fn permuted(shape: &[usize], permutation: &[usize], elem_size: usize) -> Result<Self> {
+        ensure!(elem_size > 0, "elements need at least one byte");
+        ensure!(
+            shape
+                .iter()
+                .try_fold(elem_size as u64, |size, &len| size.checked_mul(len as u64))
+                .is_some(),
+            "a {shape:?} array of {elem_size} byte elements is too big"
+        );
+        let axes = Axes::new(shape, permutation)?.collapsed();
+        let (rows, cols) = match *axes.shape() {
+            // nothing moves, like when transposing a single row
+            [] => (1, 1),
+            [len] => (1, len),
+            [rows, cols] => (rows, cols),
+            ref shape => {
+                let (cols, lines) = shape.split_last().expect("more than two axes");
+                let dims = Dimensions::new(lines.iter().product(), *cols);
+                return Ok(Dimensions {
+                    permuted: Some(axes),
+                    ..dims.with_elem_size(elem_size)
+                });
+            }
+        };
+        Ok(Dimensions::new(rows, cols).with_elem_size(elem_size))
+    }
+
+    /// The input element that ends up as output element `element`.
+    pub fn source(&self, element: u64) -> u64 {
+        match &self.permuted {
+            Some(axes) => axes.source(element),
+            None => {
+                let (rows, cols) = (self.rows as u64, self.cols as u64);
+                element % rows * cols + element / rows
+            }
+        }
+    }
+
+    /// The same matrix, but with `elem_size` byte elements.
+    pub fn with_elem_size(self, elem_size: usize) -> Self {
+        Dimensions {
+            size: self.rows as u64 * self.cols as u64 * elem_size as u64,
+            elem_size,
+            ..self
+        }
+    }
+
+    /// The dimensions of an existing matrix file with `cols` columns of
+    /// `elem_size` byte elements.
+    pub fn of_file(path: &Path, cols: usize, elem_size: usize) -> Result<Self> {
+        let size = path.metadata()?.len();
+        Dimensions::of_len(size, cols, elem_size)
+            .with_context(|| format!("can't use {} as a matrix", path.display()))
+    }
+
+    /// The dimensions of a `size` byte matrix with `cols` columns of
+    /// `elem_size` byte elements.
+    pub fn of_len(size: u64, cols: usize, elem_size: usize) -> Result<Self> {
+        let row_len = (cols as u64)
+            .checked_mul(elem_size as u64)
+            .filter(|row_len| *row_len > 0)
+            .with_context(|| format!("{cols} columns of {elem_size} bytes isn't a valid row"))?;
+        ensure!(
+            size.is_multiple_of(row_len),
+            "{size} bytes isn't a whole number of {row_len} byte rows"
+        );
+        let rows = usize::try_from(size / row_len)?;
+        Ok(Dimensions::new(rows, cols).with_elem_size(elem_size))
+    }
+
+    /// The dimensions of a generated `2**log2_size` byte matrix of
+    /// `elem_size` byte elements, as close to square as
+    /// [`from_log2_size`](Self::from_log2_size) allows.
+    pub fn generated(log2_size: u32, elem_size: usize) -> Result<Self> {
+        ensure!(
+            log2_size < usize::BITS.min(u64::BITS),
+            "2**{log2_size} bytes is too big to generate"
+        );
+        ensure!(
+            elem_size.is_power_of_two() && elem_size.ilog2() <= log2_size,
+            "the element size has to be a power of two no bigger than the file, not {elem_size}"
+        );
+        Ok(Dimensions::from_log2_size(log2_size - elem_size.ilog2()).with_elem_size(elem_size))
+    }
+
+    /// The dimensions of a `2**log2_size` byte matrix, which is as close to
+    /// square as the power of two allows (with rows >= cols).
+    ///
+    /// Panics if `2**log2_size` doesn't fit in a `usize`.
+    pub fn from_log2_size(log2_size: u32) -> Self {
+        let size = 2u64.pow(log2_size);
+        let rows = ((size as f64).sqrt().ceil() as u64).next_power_of_two() as usize;
+        let cols = size as usize / rows;
+        assert!(rows >= cols, "for convenience, wlog, rows >= cols");
+        assert_eq!(cols * rows, size as usize);
+        Dimensions::new(rows, cols)
+    }
+}
+
+/// What generated an input file. It's stored next to the input, so the input
+/// only gets regenerated when something about it changed.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct InputSpec {
+    dims: Dimensions,
+    generator: Generator,
+}
+
+/// Create the input file with `generator`, or reuse it if it was already
+/// generated with the same settings.
+pub fn setup_file(
+    dims: Dimensions,
+    generator: &Generator,
+    target_file: &Path,
+    progress: &dyn Progress,
+) -> Result<File> {
+    let spec = InputSpec {
+        dims,
+        generator: *generator,
+    };
+    let spec_path = target_file.with_extension("json");
+    let existing_spec = std::fs::read(&spec_path)
+        .ok()
+        .and_then(|json| serde_json::from_slice::<InputSpec>(&json).ok());
+
+    let handle = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(target_file)?;
+    if target_file.metadata()?.len() != dims.size || existing_spec.as_ref() != Some(&spec) {
+        println!("setting up file to work on");
+        handle.set_len(dims.size)?;
+        let mut buffered_writer = BufWriter::new(&handle);
+        generator.write(dims, &mut buffered_writer, progress)?;
+        buffered_writer.flush()?;
+        std::fs::write(&spec_path, serde_json::to_vec_pretty(&spec)?)?;
+    }
+    assert_eq!(target_file.metadata()?.len(), dims.size);
+    Ok(handle)
+}
+
+/// The transpose of a `dims` matrix in `input`, done the obvious way in
+/// memory. Only for checking the strategies against in tests and fuzzing.
+#[doc(hidden)]
+pub fn reference_transpose(
+    Dimensions {
+        rows,
+        cols,
+        elem_size,
+        ..
+    }: Dimensions,
+    input: &[u8],
+) -> Vec<u8> {
+    let mut transposed = vec![0u8; input.len()];
+    for i in 0..rows {
+        for j in 0..cols {
+            let from = (i * cols + j) * elem_size;
+            let to = (j * rows + i) * elem_size;
+            transposed[to..to + elem_size].copy_from_slice(&input[from..from + elem_size]);
+        }
+    }
+    transposed
+}
@@ -1,17 +1,35 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail, ensure};
 use clap::Parser;
 use inline_colorization::*;
-use memmap::{Mmap, MmapMut};
-use rayon::prelude::*;
+use matrix_transposer::baseline::{Comparison, Verdict, compare, load_baseline, save_baseline};
+use matrix_transposer::cancel::{Cancellation, is_cancelled};
+use matrix_transposer::checkpoint::Checkpoints;
+use matrix_transposer::cleanup::WorkFiles;
+use matrix_transposer::cli::{
+    Cli, Command, MtxArgs, SparseArgs, TextArgs, check_selected, validate,
+};
+use matrix_transposer::cost::{DeviceProfile, predict};
+use matrix_transposer::delimited::{Dialect, index, reassemble};
+use matrix_transposer::io_counters::IoCounters;
+use matrix_transposer::mtx::{transpose, verify_transposed};
+use matrix_transposer::npy::{flip, write_npy};
+use matrix_transposer::planner::{Resources, plan};
+use matrix_transposer::probe::probe;
+use matrix_transposer::progress::ProgressKind;
+use matrix_transposer::report::write_report;
+use matrix_transposer::results::{RunResults, StrategyResult};
+use matrix_transposer::sparse::{Compressed, convert, verify_converted};
+use matrix_transposer::strategies::Tiling;
+use matrix_transposer::timing::{Phase, PhaseTimings};
+use matrix_transposer::verify::{Digester, Digests, verify_with_oracle};
+use matrix_transposer::workspace::Workspace;
+use matrix_transposer::{Dimensions, Strategy, setup_file};
 use size::Size;
 use std::cmp::min;
-use std::fs::{File, OpenOptions, create_dir_all};
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::os::unix::prelude::FileExt;
-use std::path::{Path, PathBuf};
-use std::string::ToString;
-use std::time::{Duration, Instant};
-use indicatif::{ProgressBar};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
 
 const ITER_COUNT: usize = 1;
 // const size: u64 = 2u64.pow(30);
@@ -19,619 +37,646 @@ const ITER_COUNT: usize = 1;
 //
 // const cols: LazyCell<usize> = LazyCell::new(|| size as usize / rows);
 
-#[derive(Parser)]
-struct Cli {
-    /// the log2 size of the files to test over
-    #[arg(default_value_t = 20)]
-    log2_size: u32,
-
-    /// whether to show little versions of the files after each use
-    #[arg(short)]
-    verbose: bool,
-
-    /// check after each algorithm that the result is correct
-    #[arg(short)]
-    check_work: bool,
-
-    /// number of times to repeat the experiment
-    #[arg(short, default_value_t = 1)]
-    times: usize,
-
-    /// run in-memory transpose
-    #[arg(short)]
-    in_memory: bool,
-
-    /// run memmap solution
-    #[arg(short)]
-    mmap: bool,
-
-    /// run file cat solution
-    #[arg(short)]
-    join: bool,
-
-    /// run the transpose entirely on disk
-    #[arg(short)]
-    on_disk: bool,
-
-    /// entirely on disk but with a write_buffer to minimize the writes
-    #[arg(short)]
-    buff_on_disk: bool,
-
-    /// **toggle** all solutions.
-    /// Doing -a and -b, for example, will run all solutions except the
-    /// buffered one.
-    #[arg(short)]
-    all: bool,
-
-    /// keep files after they've been completed and tested
-    #[arg(short)]
-    keep_around: bool,
-}
-
-#[derive(Default, Debug, Clone, Copy)]
-struct Dimensions {
-    size: u64,
-    rows: usize,
-    cols: usize,
-}
-
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    _main(cli)
+    match cli.command {
+        Some(Command::Report { inputs, output }) => {
+            let results = inputs
+                .iter()
+                .map(|path| RunResults::load(path))
+                .collect::<Result<Vec<_>>>()?;
+            write_report(&output, &results)?;
+            println!("wrote report to {}", output.display());
+            Ok(())
+        }
+        Some(Command::Estimate {
+            shape,
+            tiling,
+            device,
+        }) => {
+            let dims = shape.dims()?;
+            let profile = load_profile(device.as_deref())?;
+            println!(
+                "the matrix is {}cols by {}rows of {} byte elements",
+                dims.cols, dims.rows, dims.elem_size
+            );
+            for strategy in Strategy::ALL
+                .into_iter()
+                .filter(|strategy| dims.permuted.is_none() || strategy.can_permute())
+            {
+                let predicted = predict(strategy, dims, tiling.tiling(), &profile);
+                print!("{}{:<18}{color_reset}", strategy.color(), strategy.name());
+                print!("{style_bold}{:>12.3?}{style_reset}", predicted.headline());
+                for phase in Phase::ALL {
+                    let time = predicted.get(phase);
+                    if !time.is_zero() {
+                        print!("  {} {time:.3?}", phase.name());
+                    }
+                }
+                println!();
+            }
+            Ok(())
+        }
+        Some(Command::Probe {
+            log2_size,
+            workdir,
+            output,
+        }) => {
+            ensure!(log2_size < u64::BITS, "2**{log2_size} bytes is too big");
+            println!(
+                "probing {} with a {} file",
+                workdir.display(),
+                Size::from_bytes(2u64.pow(log2_size))
+            );
+            let profile = probe(&workdir, 2u64.pow(log2_size), |measurement| {
+                println!("  {measurement}")
+            })?;
+            profile.save(&output)?;
+            println!("saved device numbers to {}", output.display());
+            Ok(())
+        }
+        Some(Command::Text(args)) => transpose_text(args),
+        Some(Command::Mtx(args)) => transpose_mtx(args),
+        Some(Command::ConvertSparse(args)) => convert_sparse(args),
+        Some(Command::FlipNpy { input, output }) => {
+            let header = flip(&input, output.as_deref())?;
+            println!(
+                "{} is now {:?} in {} order",
+                output.as_ref().unwrap_or(&input).display(),
+                header.shape,
+                if header.fortran_order { "Fortran" } else { "C" },
+            );
+            Ok(())
+        }
+        None => _main(cli),
+    }
 }
 
 // for mockup tests
-fn _main(mut cli: Cli) -> Result<()> {
-    let size = 2u64.pow(cli.log2_size);
-    let rows = ((size as f64).sqrt().ceil() as u64).next_power_of_two() as usize;
-    let cols = size as usize / rows;
-    assert!(rows >= cols, "for convenience, wlog, rows >= cols");
-    let dims = Dimensions { size, rows, cols };
-    assert!(cli.times > 0, "must run a positive amount of runs");
-    if cli.all {
-        cli.in_memory ^= true;
-        cli.mmap ^= true;
-        cli.on_disk ^= true;
-        cli.buff_on_disk ^= true;
-        cli.join ^= true;
+fn _main(cli: Cli) -> Result<()> {
+    let (dims, npy, mut selected) = validate(&cli)?;
+    let Dimensions {
+        size,
+        rows,
+        cols,
+        elem_size,
+        ..
+    } = dims;
+    let generator = cli.generator();
+
+    let mut workspace = Workspace::new(&cli.workdir, cli.tempdir.clone())
+        .with_atomic_outputs(cli.atomic)
+        .with_checkpoints(cli.checkpoints())
+        .with_tiling(cli.tiling.tiling());
+    workspace.create()?;
+    // set on Ctrl-C, so a run stops between rows and still cleans up and
+    // reports what it got through
+    let cancel = Cancellation::on_signals()?;
+
+    let progress = if cli.quiet {
+        ProgressKind::Silent
+    } else {
+        cli.progress
     }
-    assert!(
-        !cli.check_work || cli.in_memory,
-        "{color_red}the in_memory solution is used as the reference solution, and therefore must be on to check work.{color_reset}"
-    );
+    .sink();
+
+    // load these up front so a typo doesn't cost a whole run
+    let profile = (cli.estimate || cli.device.is_some())
+        .then(|| load_profile(cli.device.as_deref()))
+        .transpose()?;
+    // only measured numbers, not guesses
+    let device = profile.as_ref().filter(|_| cli.device.is_some());
+    let baseline = cli
+        .compare_baseline
+        .as_deref()
+        .map(|name| load_baseline(&cli.baseline_dir, name))
+        .transpose()?;
 
     // setup file
     print!("{color_blue}");
-    println!(
-        "running test with filesize 2**{} == {} over {ITER_COUNT} iters",
-        cli.log2_size,
-        Size::from_bytes(size),
-    );
-    println!("the matrix is {cols}cols by {rows}rows");
-    print!("{color_reset}{style_reset}\n");
-    assert_eq!(cols * rows, size as usize);
+    match &cli.shape.input {
+        Some(input) => println!(
+            "running test on {} == {} over {ITER_COUNT} iters",
+            input.display(),
+            Size::from_bytes(size),
+        ),
+        None if cli.shape.array_shape.is_some() => println!(
+            "running test with a generated {} array over {ITER_COUNT} iters",
+            Size::from_bytes(size),
+        ),
+        None => println!(
+            "running test with filesize 2**{} == {} over {ITER_COUNT} iters",
+            cli.shape.log2_size,
+            Size::from_bytes(size),
+        ),
+    }
+    match &dims.permuted {
+        Some(axes) => println!(
+            "the permute comes down to axes {:?} of {elem_size} byte elements, put in order {:?}",
+            axes.shape(),
+            axes.permutation()
+        ),
+        None => println!("the matrix is {cols}cols by {rows}rows of {elem_size} byte elements"),
+    }
+    if cli.shape.input.is_none() {
+        println!("generated with {generator:?}");
+    }
+    println!("{color_reset}{style_reset}");
 
+    // deletes whatever outputs are left over if anything below fails
+    let mut work_files = WorkFiles::new(cli.keep_around);
     print!("{color_green}");
-    let target_file = PathBuf::from("input_file.md");
-    let mut input_handle = setup_file(dims, &target_file)?;
-    print!("{color_reset}{style_reset}\n");
+    // a .npy input is read in place, from where its payload starts
+    let (target_file, mut input_handle) = match &cli.shape.input {
+        Some(input) => (input.clone(), File::open(input)?),
+        None => {
+            let target_file = workspace.input_path();
+            let input_handle = setup_file(dims, &generator, &target_file, progress.as_ref())?;
+            (target_file, input_handle)
+        }
+    };
+    println!("{color_reset}{style_reset}");
     if cli.verbose {
         println!("input file looks like this:");
         sample_file(dims, &mut input_handle)?;
     }
-
-    // in memory solution
-    let mut mem_file = if cli.in_memory {
-        print!("{color_magenta}");
-        println!("starting in-memory transpose");
-
-        let mut total_duration = Duration::from_secs(0);
-        let mut mem_file = File::open(PathBuf::from("input_file.md"))?;
-        for _ in 0..cli.times {
-            let (new_mem_file, mem_dur) = in_memory(dims, &mut input_handle)?;
-            total_duration += mem_dur;
-            mem_file = new_mem_file;
-        }
-        if cli.times > 1 {
-            println!(
-                "{style_bold}On average it took {:?}",
-                total_duration / cli.times as u32
-            );
-        }
-        print_throughput(size * cli.times as u64, total_duration);
-        print!("{color_reset}{style_reset}\n");
-
-        if cli.verbose {
-            println!("in_memory output looks like this:");
-            sample_file(dims, &mut mem_file)?;
-        }
-        if cli.check_work {
-            mem_file.seek(SeekFrom::Start(0))?;
-            let mut temp_storage = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(PathBuf::from("temp_transpose_file.md"))?;
-            std::io::copy(&mut mem_file, &mut temp_storage)?;
-            temp_storage.seek(SeekFrom::Start(0))?;
-            let (mut new_mem_file, _) = in_memory(dims, &mut temp_storage)?;
-            assert!(file_eq_assert(&mut mem_file, &mut new_mem_file)?);
-            std::fs::rename(
-                PathBuf::from("temp_transpose_file.md"),
-                PathBuf::from("in_memory.md"),
-            )?;
-        }
-        mem_file
-    } else {
-        // dummy case for type checking; flag allocation should prevent the
-        // correctness assert from happening if this path is taken
-        File::open(&target_file)?
+    // there's no formula for the bytes of a user supplied input, so digest
+    // it while the first run reads it and check every output against that
+    let oracle = cli.shape.input.is_none().then(|| generator.oracle(dims));
+    let mut digester = match (&oracle, cli.check_work) {
+        (None, true) => Some(Digester::new(dims)?),
+        _ => None,
     };
-
-    // memmap solution
-    if cli.mmap {
-        print!("{color_yellow}");
-        println!("starting memmap solution");
-        let mut total_duration = Duration::from_secs(0);
-        let mut mmap_file = File::open(PathBuf::from("input_file.md"))?;
-        for _ in 0..cli.times {
-            let (new_mmap_file, mmap_dur) = mmap_solution(dims, &target_file)?;
-            total_duration += mmap_dur;
-            mmap_file = new_mmap_file;
-        }
-        if cli.times > 1 {
-            println!(
-                "{style_bold}On average it took {:?}",
-                total_duration / cli.times as u32
-            );
-        }
-        print_throughput(size * cli.times as u64, total_duration);
-        print!("{color_reset}{style_reset}\n");
-        if cli.verbose {
-            println!("mmap file looks like this:");
-            sample_file(dims, &mut mmap_file)?;
-        }
-        if cli.check_work && cli.in_memory {
-            assert!(file_eq_assert(&mut mem_file, &mut mmap_file)?);
-        }
-        if !cli.keep_around {
-            drop(mmap_file);
-            std::fs::remove_file(PathBuf::from("mmap.md"))?;
+    let mut digests = None;
+
+    if cli.auto {
+        // planned once the input is there, so it doesn't count as free space
+        let plan = plan(
+            dims,
+            Resources::of_system(&workspace)?,
+            workspace.checkpoints,
+            device,
+        )?;
+        println!(
+            "{style_bold}picked the {}:{style_reset}",
+            plan.strategy.description()
+        );
+        for reason in &plan.reasons {
+            println!("  {reason}");
         }
+        println!();
+        selected = selected.map(|(_, strategy)| (strategy == plan.strategy, strategy));
+        workspace = workspace.with_tiling(plan.tiling);
+        check_selected(&selected, dims, workspace.checkpoints)?;
     }
-
-    // naive entirely on disk
-    if cli.on_disk {
-        print!("{color_bright_blue}");
-        println!("starting transpose entirely on disk");
-        #[cfg(unix)]
-        {
-            let mut total_duration = Duration::from_secs(0);
-            let mut on_disk_result_file = File::open(PathBuf::from("input_file.md"))?;
-            for _ in 0..cli.times {
-                let (new_joined_file, disk_dur) = disk_io_solution(dims, &target_file)?;
-                total_duration += disk_dur;
-                on_disk_result_file = new_joined_file;
-            }
-            if cli.times > 1 {
-                println!(
-                    "{style_bold}On average it took {:?}",
-                    total_duration / cli.times as u32
-                );
-            }
-            print_throughput(size * cli.times as u64, total_duration);
-            print!("{color_reset}{style_reset}\n");
-            if cli.verbose {
-                println!("disk manipulated file looks like this:");
-                sample_file(dims, &mut on_disk_result_file)?;
-            }
-            if cli.check_work && cli.in_memory {
-                assert!(file_eq_assert(&mut mem_file, &mut on_disk_result_file)?);
-            }
-            if !cli.keep_around {
-                drop(on_disk_result_file);
-                std::fs::remove_file(PathBuf::from("disk_io.md"))?;
-            }
+    let mut results = RunResults::new(dims, cli.shape.input.is_none().then_some(generator));
+    results.device = device.cloned();
+    results.input = cli
+        .shape
+        .input
+        .as_deref()
+        .map(Path::canonicalize)
+        .transpose()?;
+    let mut cancelled = None;
+    for strategy in selected
+        .into_iter()
+        .filter_map(|(enabled, strategy)| enabled.then_some(strategy))
+    {
+        print!("{}", strategy.color());
+        println!("starting {}", strategy.description());
+
+        let mut runs = Vec::with_capacity(cli.times);
+        let mut io = Vec::with_capacity(cli.times);
+        let mut output_file = None;
+        let output_path = workspace.output_path(strategy);
+        // left over if a run fails before publishing
+        let partial_path = workspace.partial_path(strategy);
+        // a checkpointed output is only worth anything if it's still there
+        // after a failure
+        if workspace.checkpoints == Checkpoints::Off {
+            work_files.track(&output_path);
+            work_files.track(&partial_path);
         }
-        #[cfg(not(unix))]
-        println!("function not available on non-unix systems")
-    }
-
-    // buffered but entirely on disk
-    if cli.buff_on_disk {
-        print!("{color_bright_green}");
-        println!("starting transpose on disk but buffered");
-        #[cfg(unix)]
-        {
-            let mut total_duration = Duration::from_secs(0);
-            let mut buffered_disk_result_file = File::open(PathBuf::from("input_file.md"))?;
-            for _ in 0..cli.times {
-                let (new_joined_file, buff_disk_dur) =
-                    buffered_disk_io_solution(dims, &target_file)?;
-                total_duration += buff_disk_dur;
-                buffered_disk_result_file = new_joined_file;
-            }
-            if cli.times > 1 {
-                println!(
-                    "{style_bold}On average it took {:?}",
-                    total_duration / cli.times as u32
-                );
-            }
-            print_throughput(size * cli.times as u64, total_duration);
-            print!("{color_reset}{style_reset}\n");
-            if cli.verbose {
-                println!("buffered disk manipulated file looks like this:");
-                sample_file(dims, &mut buffered_disk_result_file)?;
-            }
-            if cli.check_work && cli.in_memory {
-                assert!(file_eq_assert(
-                    &mut mem_file,
-                    &mut buffered_disk_result_file
-                )?);
+        for _ in 0..cli.times {
+            let io_before = IoCounters::of_this_process();
+            let run = match digester.as_mut() {
+                Some(digester) => strategy.run_digesting(
+                    dims,
+                    &target_file,
+                    &workspace,
+                    digester,
+                    progress.as_ref(),
+                    &cancel,
+                ),
+                None => strategy.run(dims, &target_file, &workspace, progress.as_ref(), &cancel),
+            };
+            let (new_output_file, timings) = match run {
+                Ok(run) => run,
+                Err(error) if is_cancelled(&error) => {
+                    cancelled = Some(strategy);
+                    break;
+                }
+                Err(error) => return Err(error),
+            };
+            if let (Some(before), Some(after)) = (io_before, IoCounters::of_this_process()) {
+                io.push(after.since(before));
             }
-            if !cli.keep_around {
-                drop(buffered_disk_result_file);
-                std::fs::remove_file(PathBuf::from("disk_io.md"))?;
+            if let Some(digester) = digester.take() {
+                digests = Some(match digester.finish() {
+                    Some(digests) => digests,
+                    // a resumed run skips what it had already done
+                    None => {
+                        println!("the run didn't read all of the input, digesting it separately");
+                        Digests::of_input(dims, &input_handle)?
+                    }
+                });
             }
+            runs.push(timings);
+            output_file = Some(new_output_file);
         }
-        #[cfg(not(unix))]
-        println!("function not available on non-unix systems")
-    }
-
-    // multiple temp files concatenated solution
-    if cli.join {
-        print!("{color_cyan}");
-        println!("starting transpose with temp files");
-        let mut total_duration = Duration::from_secs(0);
-        let mut joined_file = File::open(PathBuf::from("input_file.md"))?;
-        for _ in 0..cli.times {
-            let (new_joined_file, temp_dur) = join_file_handles(dims, &mut input_handle)?;
-            total_duration += temp_dur;
-            joined_file = new_joined_file;
-        }
-        if cli.times > 1 {
+        if cancelled.is_some() {
+            progress.finish();
+            if !runs.is_empty() {
+                print_timings(size, &runs);
+                results.strategies.push(StrategyResult {
+                    strategy: strategy.name().to_string(),
+                    runs,
+                    io,
+                    cancelled: true,
+                });
+            }
             println!(
-                "{style_bold}On average it took {:?}",
-                total_duration / cli.times as u32
+                "{color_yellow}{} cancelled{color_reset}{style_reset}",
+                strategy.name()
             );
+            break;
+        }
+        let mut output_file = output_file.expect("must run a positive amount of runs");
+        print_timings(size, &runs);
+        if let Some(profile) = profile.as_ref().filter(|_| cli.estimate) {
+            print_prediction(predict(strategy, dims, workspace.tiling, profile), &runs);
         }
-        print_throughput(size * cli.times as u64, total_duration);
-        print!("{color_reset}{style_reset}\n");
+        println!("{color_reset}{style_reset}");
+        results.strategies.push(StrategyResult {
+            strategy: strategy.name().to_string(),
+            runs,
+            io,
+            cancelled: false,
+        });
+
         if cli.verbose {
-            println!("temp file looks like this:");
-            sample_file(dims, &mut joined_file)?;
+            println!("{} output looks like this:", strategy.description());
+            sample_file(dims, &mut output_file)?;
         }
-        if cli.check_work && cli.in_memory {
-            assert!(file_eq_assert(&mut mem_file, &mut joined_file)?);
+        if cli.check_work {
+            let mismatch = match (&oracle, &digests) {
+                (Some(oracle), _) => verify_with_oracle(dims, &output_file, oracle)?
+                    .map(|mismatch| mismatch.to_string()),
+                (None, Some(digests)) => digests
+                    .verify_transposed(dims, &output_file)?
+                    .map(|mismatch| mismatch.to_string()),
+                (None, None) => {
+                    unreachable!("digests are made by the first run whenever there's no oracle")
+                }
+            };
+            if let Some(mismatch) = mismatch {
+                bail!(
+                    "{color_red}{} output isn't the transpose of the input: {mismatch}{color_reset}",
+                    strategy.name()
+                );
+            }
         }
-        if !cli.keep_around {
-            drop(joined_file);
-            std::fs::remove_file(PathBuf::from("catted_cols.md"))?;
+        // a kept output of a .npy input is only useful as a .npy file too
+        if let Some((header, _)) = npy.as_ref().filter(|_| cli.keep_around) {
+            let npy_path = workspace.npy_path(strategy);
+            let permutation = cli.shape.permutation(header.shape.len());
+            write_npy(&header.permuted(&permutation), &mut output_file, &npy_path)?;
+            std::fs::remove_file(&output_path)?;
+            println!("kept the transpose as {}", npy_path.display());
         }
+        drop(output_file);
+        work_files.remove(&output_path)?;
+        work_files.remove(&partial_path)?;
     }
 
-    if !cli.keep_around && cli.in_memory {
-        drop(mem_file);
-        std::fs::remove_file(PathBuf::from("in_memory.md"))?;
+    if let Some(baseline) = baseline {
+        print_comparisons(&compare(&baseline, &results)?);
+    }
+    // a cancelled run is incomplete, so it's no good to compare against later
+    if let Some(name) = cli.save_baseline.as_ref().filter(|_| cancelled.is_none()) {
+        let path = save_baseline(&cli.baseline_dir, name, &results)?;
+        println!("saved baseline {name:?} to {}", path.display());
+    }
+    if let Some(path) = &cli.results {
+        results.save(path)?;
+        println!("saved results to {}", path.display());
+    }
+    if let Some(strategy) = cancelled {
+        // dropping `work_files` on the way out deletes the interrupted outputs
+        bail!("interrupted while running {}", strategy.name());
     }
 
     Ok(())
 }
 
-fn setup_file(Dimensions { size, .. }: Dimensions, target_file: &Path) -> Result<File> {
-    let handle = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&target_file)?;
-    if target_file.metadata()?.len() != size {
-        println!("setting up file to work on");
-        handle.set_len(size)?;
-        let mut buffered_writer = BufWriter::new(&handle);
-        let letters = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!@#$^&*()-+[]"
-            .to_string();
-
-        let bar = ProgressBar::new(size);
-        let mut written = 0;
-
-        while written < size {
-            let writeable_index = min(letters.len(), (size - written) as usize);
-            buffered_writer.write(letters[..writeable_index].as_ref())?;
-            bar.inc(writeable_index as u64);
-            written += writeable_index as u64;
+/// Transpose a delimited text file by indexing it, transposing the index
+/// with the chosen method and copying the fields over in the new order.
+fn transpose_text(args: TextArgs) -> Result<()> {
+    let dialect = match args.delimiter {
+        Some(delimiter) => {
+            ensure!(delimiter.is_ascii(), "the delimiter has to be ASCII");
+            Dialect {
+                delimiter: delimiter as u8,
+                ..Dialect::CSV
+            }
         }
-        buffered_writer.flush()?;
-        bar.finish_and_clear();
+        None => Dialect::of_path(&args.input),
+    };
+    let workspace = Workspace::new(&args.workdir, None).with_tiling(args.tiling.tiling());
+    workspace.create()?;
+    let cancel = Cancellation::on_signals()?;
+    let progress = if args.quiet {
+        ProgressKind::Silent
+    } else {
+        ProgressKind::Bar
     }
-    assert_eq!(target_file.metadata()?.len(), size);
-    Ok(handle)
+    .sink();
+    let mut work_files = WorkFiles::new(args.keep_around);
+    let index_path = work_files.track(workspace.text_index_path()).to_path_buf();
+    work_files.track(workspace.output_path(args.method));
+
+    let mut timings = PhaseTimings::default();
+    let dims = timings.time(Phase::Read, || {
+        index(
+            &args.input,
+            dialect,
+            &index_path,
+            progress.as_ref(),
+            &cancel,
+        )
+    })?;
+    println!(
+        "{} has {} rows of {} fields",
+        args.input.display(),
+        dims.rows,
+        dims.cols
+    );
+    let digests = args
+        .check_work
+        .then(|| Digests::of_input(dims, &File::open(&index_path)?))
+        .transpose()?;
+    let (mut transposed_index, method_timings) =
+        args.method
+            .run(dims, &index_path, &workspace, progress.as_ref(), &cancel)?;
+    timings += method_timings;
+    if let Some(digests) = digests
+        && let Some(mismatch) = digests.verify_transposed(dims, &transposed_index)?
+    {
+        bail!(
+            "{color_red}{} didn't transpose the index: {mismatch}{color_reset}",
+            args.method.name()
+        );
+    }
+    let output = timings.time(Phase::Write, || {
+        reassemble(
+            &args.input,
+            dims,
+            &mut transposed_index,
+            dialect,
+            &args.output,
+            progress.as_ref(),
+            &cancel,
+        )
+    })?;
+    timings.time(Phase::Flush, || output.sync_all())?;
+    drop(transposed_index);
+    timings.time(Phase::Cleanup, || work_files.remove_all())?;
+
+    print!("{}", args.method.color());
+    print_timings(args.input.metadata()?.len(), &[timings]);
+    println!(
+        "{color_reset}wrote the transpose to {}",
+        args.output.display()
+    );
+    Ok(())
 }
 
-fn in_memory(
-    Dimensions { size, rows, cols }: Dimensions,
-    input_handle: &mut File,
-) -> Result<(File, Duration)> {
-    input_handle.seek(SeekFrom::Start(0))?;
-    let path: PathBuf = PathBuf::from("in_memory.md");
-    let mut output_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&path)?;
-
-    let start_time = Instant::now();
-
-    let mut input_buff = Vec::with_capacity(size as usize);
-    let num_read_bytes = input_handle.read_to_end(&mut input_buff)?;
-    assert_eq!(num_read_bytes, size as usize);
-    let mut output_buff = vec![0; size as usize];
-    // transpose the data in memory
-    let bar = ProgressBar::new(rows as u64);
-    for i in 0..rows {
-        for j in 0..cols {
-            output_buff[j * rows + i] = input_buff[i * cols + j];
+/// Transpose a Matrix Market file `args.times` times, timing every run like
+/// the dense methods are.
+fn transpose_mtx(args: MtxArgs) -> Result<()> {
+    ensure!(args.times > 0, "must run a positive amount of runs");
+    let workspace = Workspace::new(".", args.tempdir.clone());
+    let cancel = Cancellation::on_signals()?;
+    let progress = if args.quiet {
+        ProgressKind::Silent
+    } else {
+        ProgressKind::Bar
+    }
+    .sink();
+    let memory = args
+        .memory
+        .checked_mul(2usize.pow(20))
+        .context("--memory is too big")?;
+
+    let mut runs = Vec::with_capacity(args.times);
+    let mut header = None;
+    for _ in 0..args.times {
+        let (_, new_header, timings) = transpose(
+            &args.input,
+            &args.output,
+            &workspace.scratch_dir("sorted_runs"),
+            memory,
+            progress.as_ref(),
+            &cancel,
+        )?;
+        runs.push(timings);
+        header = Some(new_header);
+    }
+    let header = header.expect("must run a positive amount of runs");
+    println!(
+        "{} is {}x{} with {} {} {} entries",
+        args.input.display(),
+        header.cols,
+        header.rows,
+        header.entries,
+        header.field.name(),
+        header.symmetry.name()
+    );
+    print_timings(args.input.metadata()?.len(), &runs);
+    println!(
+        "{style_reset}wrote the transpose to {}",
+        args.output.display()
+    );
+    if args.check_work {
+        if let Some(mismatch) = verify_transposed(&args.input, &args.output)? {
+            bail!(
+                "{color_red}the output isn't the transpose of the input: {mismatch}{color_reset}"
+            );
         }
-        bar.inc(1);
+        println!("{color_green}checked the transpose{color_reset}");
     }
-    assert_eq!(size as usize, output_file.write(&output_buff)?);
-    output_file.flush()?;
-    output_file.sync_all()?;
-    bar.finish_and_clear();
-    let duration = start_time.elapsed();
-
-    println!("in_memory time: {:?}", duration);
-
-    Ok((output_file, duration))
+    Ok(())
 }
 
-fn mmap_solution(
-    Dimensions { rows, cols, size }: Dimensions,
-    input_path: &Path,
-) -> Result<(File, Duration)> {
-    let input_file = File::open(input_path)?;
-    let target_path: PathBuf = PathBuf::from("mmap.md");
-    // std::fs::copy(input_path, &target_path)?;
-    let output_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&target_path)?;
-    output_file.set_len(size)?;
-    let input_mmap = unsafe { Mmap::map(&input_file)? };
-    let mut output_mmap = unsafe { MmapMut::map_mut(&output_file)? };
+/// Convert a compressed sparse matrix `args.times` times, timing every run
+/// like the dense methods are.
+fn convert_sparse(args: SparseArgs) -> Result<()> {
+    ensure!(args.times > 0, "must run a positive amount of runs");
+    let input = Compressed::open(&args.input)?;
+    let cancel = Cancellation::on_signals()?;
+    let progress = if args.quiet {
+        ProgressKind::Silent
+    } else {
+        ProgressKind::Bar
+    }
+    .sink();
+    let tiling = Tiling {
+        band_rows: args.segment_len,
+        pass_cols: args.pass_len,
+    };
+    println!(
+        "{} is a {}x{} {} matrix with {} entries of {} bytes",
+        args.input.display(),
+        input.shape[0],
+        input.shape[1],
+        input.format.name(),
+        input.nnz(),
+        input.elem_size()
+    );
 
-    let start_time = Instant::now();
-    let bar = ProgressBar::new(rows as u64);
-    for i in 0..rows {
-        for j in 0..cols {
-            output_mmap[j * rows + i] = input_mmap[i * cols + j];
+    let mut runs = Vec::with_capacity(args.times);
+    let mut output = None;
+    for _ in 0..args.times {
+        let (converted, timings) =
+            convert(&input, &args.output, tiling, progress.as_ref(), &cancel)?;
+        runs.push(timings);
+        output = Some(converted);
+    }
+    let output = output.expect("must run a positive amount of runs");
+    let size = input.nnz() * input.elem_size() as u64;
+    print_timings(size, &runs);
+    println!(
+        "{style_reset}wrote it as {} to {}",
+        output.format.name(),
+        args.output.display()
+    );
+    if args.check_work {
+        if let Some(mismatch) = verify_converted(&input, &output)? {
+            bail!("{color_red}the output isn't the input converted: {mismatch}{color_reset}");
         }
-        bar.inc(1);
+        println!("{color_green}checked the conversion{color_reset}");
     }
-
-    output_mmap.flush()?;
-    output_file.sync_all()?;
-    bar.finish_and_clear();
-
-    let duration = start_time.elapsed();
-    println!("memmap time: {:?}", duration);
-
-    Ok((output_file, duration))
+    Ok(())
 }
 
-#[cfg(unix)]
-fn disk_io_solution(
-    Dimensions { rows, cols, size }: Dimensions,
-    input_path: &Path,
-) -> Result<(File, Duration)> {
-    let input_file = File::open(&input_path)?;
-    let target_path: PathBuf = PathBuf::from("disk_io.md");
-    let mut output_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&target_path)?;
-    output_file.set_len(size)?;
-
-    let start_time = Instant::now();
-    let mut input_row_buf = vec![0u8; cols];
-    let bar = ProgressBar::new(rows as u64);
-    for i in 0..rows {
-        input_file.read_at(&mut input_row_buf, (i * cols) as u64)?;
-        for j in 0..cols {
-            output_file.write_at(&[input_row_buf[j]], (j * rows + i) as u64)?;
-        }
-        bar.inc(1);
+fn sample_file(
+    Dimensions {
+        rows,
+        cols,
+        elem_size,
+        input_offset,
+        ..
+    }: Dimensions,
+    file: &mut File,
+) -> Result<()> {
+    file.seek(SeekFrom::Start(input_offset))?;
+    let row_len = cols * elem_size;
+    let read_in_bytes = min(8usize, row_len);
+    let mut input_buf = vec![0u8; read_in_bytes];
+    for _ in 0..min(8usize, rows) {
+        file.read_exact(input_buf.as_mut_slice())?;
+        println!("{}", String::from_utf8_lossy(input_buf.as_slice()));
+        file.seek_relative(row_len as i64 - input_buf.len() as i64)?
     }
-
-    output_file.flush()?;
-    output_file.sync_all()?;
-    bar.finish_and_clear();
-
-    let duration = start_time.elapsed();
-    println!("on_disk naive time: {:?}", duration);
-
-    Ok((output_file, duration))
+    Ok(())
 }
 
-#[cfg(unix)]
-fn buffered_disk_io_solution(
-    Dimensions { rows, cols, size }: Dimensions,
-    input_path: &Path,
-) -> Result<(File, Duration)> {
-    const BUFF_SIZE: usize = 2usize.pow(10);
-    let input_file = File::open(&input_path)?;
-    let mut input_file_reader = BufReader::with_capacity(BUFF_SIZE * 30, input_file);
-    let target_path: PathBuf = PathBuf::from("disk_io.md");
-    let mut output_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&target_path)?;
-    output_file.set_len(size)?;
-
-    let start_time = Instant::now();
-
-    let mut output_buff_buff: Vec<Vec<u8>> = vec![Vec::with_capacity(BUFF_SIZE); cols];
-    let mut input_row_buff = vec![0; cols];
-    let mut write_index = 0;
-    let bar = ProgressBar::new(rows as u64);
-
-    for row_index in 0..rows {
-        input_file_reader.read(&mut input_row_buff)?;
-        (&mut output_buff_buff, &input_row_buff)
-            .into_par_iter()
-            .for_each(|(row_buf, row_entry)| row_buf.push(*row_entry));
-        if output_buff_buff.first().unwrap().len() >= BUFF_SIZE || row_index == rows - 1 {
-            output_buff_buff
-                .par_iter()
-                .enumerate()
-                .try_for_each(|(column_index, col_buf)| {
-                    output_file
-                        .write_at(col_buf, (write_index + column_index * rows) as u64)
-                        .and_then(|_| Ok(()))
-                })?;
-            output_buff_buff
-                .par_iter_mut()
-                .for_each(|row_buf| row_buf.clear());
-            write_index = row_index + 1;
+/// The device numbers at `path`, or the built-in guesses without one.
+fn load_profile(path: Option<&Path>) -> Result<DeviceProfile> {
+    match path {
+        Some(path) => {
+            println!("using device numbers from {}", path.display());
+            DeviceProfile::load(path)
+        }
+        None => {
+            println!(
+                "{color_yellow}using guesses for a NVMe SSD, pass --device for better estimates{color_reset}"
+            );
+            Ok(DeviceProfile::default())
         }
-        bar.inc(1);
     }
-    output_file.flush()?;
-    output_file.sync_all()?;
-    bar.finish_and_clear();
-
-    let duration = start_time.elapsed();
-    println!("on_disk buffered time: {:?}", duration);
-
-    Ok((output_file, duration))
 }
 
-fn join_file_handles(
-    Dimensions { size, rows, cols }: Dimensions,
-    input_handle: &mut File,
-) -> Result<(File, Duration)> {
-    input_handle.seek(SeekFrom::Start(0))?;
-    let temp_dir = std::env::temp_dir().join("transpose_columns");
-    create_dir_all(&temp_dir)?;
-    let target_output = PathBuf::from("catted_cols.md");
-    let mut output_file = OpenOptions::new()
-        .write(true)
-        .read(true)
-        .create(true)
-        .truncate(true)
-        .open(&target_output)?;
-    output_file.set_len(size)?;
-
-    let start_time_with_temps = Instant::now();
-    let io_result = || -> Result<(File, Duration)> {
-        let mut new_row_file_handles = (0..rows)
-            .map(|i| {
-                let temp_file_name = temp_dir.join(format!("row-{}.md", i));
-                let temp_file_handle = OpenOptions::new()
-                    .write(true)
-                    .read(true)
-                    .create(true)
-                    .truncate(true)
-                    .open(&temp_file_name)?;
-                let temp_file_buff_writer = BufWriter::new(temp_file_handle);
-                Ok((temp_file_name, temp_file_buff_writer))
-            })
-            .collect::<Result<Vec<(PathBuf, BufWriter<File>)>>>()?;
-
-        let start_time = Instant::now();
-        let mut row_buf = vec![0u8; cols];
-        let bar = ProgressBar::new(rows as u64);
-        // read in row by row and splice them into separate column files
-        for _ in 0..rows {
-            input_handle.read(&mut row_buf)?;
-            (&mut row_buf, &mut new_row_file_handles)
-                .into_par_iter()
-                .for_each(|(input_byte, output_row)| {
-                    output_row.1.write(&[*input_byte]).unwrap();
-                });
-            bar.inc(1);
-        }
-        bar.finish_and_clear();
-
-        //concatenate each column file into one base file
-        let bar = ProgressBar::new(rows as u64);
-        new_row_file_handles
-            .into_iter()
-            .map(|(handle, mut writer)| {
-                writer.flush()?; // ensure the writer is actually written out
-                std::io::copy(&mut File::open(&handle)?, &mut output_file)?;
-                bar.inc(1);
-                Ok(handle)
-            })
-            .collect::<Result<Vec<PathBuf>>>()?;
-        output_file.flush()?;
-        output_file.sync_all()?;
-        bar.finish_and_clear();
-        let duration = start_time.elapsed();
-        Ok((output_file, duration))
-    }();
-
-    let delete_result = || -> Result<_> {
-        (0..rows)
-            .map(|i| {
-                let temp_file_name = temp_dir.join(format!("row-{}.md", i));
-                if temp_file_name.exists() {
-                    std::fs::remove_file(&temp_file_name)?
-                }
-                Ok(())
-            })
-            .fold(anyhow::Ok(()), |acc, res| acc.and(res))
-    }();
-
-    let (output_file, duration) = delete_result.and(io_result)?;
-    let duration_with_temp = start_time_with_temps.elapsed();
-
+/// Print how far the `predicted` headline time was off from the average one
+/// of `runs`.
+fn print_prediction(predicted: PhaseTimings, runs: &[PhaseTimings]) {
+    let actual = runs
+        .iter()
+        .map(|timings| timings.headline().as_secs_f64())
+        .sum::<f64>()
+        / runs.len() as f64;
+    let predicted = predicted.headline().as_secs_f64();
     println!(
-        "tranpose with temp files time: {:?}\ntime with all cleanup {:?}",
-        duration, duration_with_temp
+        "predicted {:?}, {:+.0}% off",
+        Duration::from_secs_f64(predicted),
+        (predicted / actual - 1.0) * 100.0
     );
-
-    Ok((output_file, duration))
 }
 
-fn sample_file(Dimensions { cols, .. }: Dimensions, file: &mut File) -> Result<()> {
-    file.seek(SeekFrom::Start(0))?;
-    let read_in_bytes = min(8usize, cols);
-    let mut input_buf = vec![0u8; read_in_bytes];
-    for _ in 0..read_in_bytes {
-        file.read(input_buf.as_mut_slice())?;
-        println!("{}", String::from_utf8_lossy(input_buf.as_slice()));
-        file.seek_relative(cols as i64 - input_buf.len() as i64)?
+/// Print the per-phase averages over all `runs`, followed by the headline
+/// time and the throughput that goes with it.
+fn print_timings(size: u64, runs: &[PhaseTimings]) {
+    let times = runs.len();
+    let mut total_timings = PhaseTimings::default();
+    for timings in runs {
+        total_timings += *timings;
     }
-    Ok(())
-}
-
-fn file_eq_assert(file_a: &mut File, file_b: &mut File) -> Result<bool> {
-    if file_a.metadata()?.len() != file_b.metadata()?.len() {
-        return Ok(false);
+    let average = total_timings / times as u32;
+    for phase in Phase::ALL {
+        let counted = if phase.counts_toward_headline() {
+            ""
+        } else {
+            " (not counted)"
+        };
+        println!("  {:<10} {:?}{counted}", phase.name(), average.get(phase));
     }
-
-    file_a.seek(SeekFrom::Start(0))?;
-    file_b.seek(SeekFrom::Start(0))?;
-
-    let input_size = 2usize.pow(10);
-    let mut input_buf_a = Vec::with_capacity(input_size);
-    let mut input_buf_b = Vec::with_capacity(input_size);
-
-    while file_a.read(&mut input_buf_a)? > 0 {
-        file_b.read(&mut input_buf_b)?;
-        if input_buf_a != input_buf_b {
-            return Ok(false);
-        }
+    if times > 1 {
+        println!("{style_bold}On average it took {:?}", average.headline());
+    } else {
+        println!("{style_bold}It took {:?}", average.headline());
     }
+    println!(
+        "{style_reset}total including setup and cleanup {:?}",
+        average.total()
+    );
+    print_throughput(size * times as u64, total_timings.headline());
+}
 
-    Ok(true)
+fn print_comparisons(comparisons: &[Comparison]) {
+    println!("{style_bold}compared to baseline:{style_reset}");
+    for comparison in comparisons {
+        let (color, verdict) = match comparison.verdict {
+            Verdict::Improved => (color_green, "improved"),
+            Verdict::Regressed => (color_red, "regressed"),
+            Verdict::NoChange => (color_reset, "no change"),
+            Verdict::Inconclusive if comparison.change.is_none() => {
+                (color_yellow, "inconclusive, the baseline took no time")
+            }
+            Verdict::Inconclusive => (color_yellow, "inconclusive, needs -t 2 or more"),
+        };
+        println!(
+            "  {:<18} {:>10.3?} -> {:>10.3?} {color}{:>8} ({verdict}){color_reset}",
+            comparison.strategy,
+            Duration::from_secs_f64(comparison.baseline.mean),
+            Duration::from_secs_f64(comparison.current.mean),
+            comparison
+                .change
+                .map_or("n/a".to_string(), |change| format!(
+                    "{:+.2}%",
+                    change * 100.0
+                )),
+        );
+    }
 }
 
 fn print_throughput(bytes_processed: u64, total_duration: Duration) {
@@ -647,10 +692,22 @@ fn calculate_index(i: usize, len: usize) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use matrix_transposer::baseline::DEFAULT_BASELINE_DIR;
+    use matrix_transposer::cli::{ShapeArgs, TilingArgs};
+    use matrix_transposer::generate::{DEFAULT_DENSITY, GeneratorKind};
+    use std::path::PathBuf;
     #[test]
     fn test_all() {
         let cli = Cli {
-            log2_size: 5, // should be odd to test for cols != rows
+            command: None,
+            shape: ShapeArgs {
+                log2_size: 5, // should be odd to test for cols != rows
+                input: None,
+                cols: None,
+                elem_size: 1,
+                array_shape: None,
+                permute: None,
+            },
             verbose: true,
             check_work: true,
             times: 3,
@@ -660,7 +717,28 @@ mod tests {
             on_disk: true,
             buff_on_disk: true,
             all: false,
+            auto: false,
+            tiling: TilingArgs {
+                band_rows: None,
+                pass_cols: None,
+            },
             keep_around: false,
+            save_baseline: None,
+            compare_baseline: None,
+            baseline_dir: PathBuf::from(DEFAULT_BASELINE_DIR),
+            generator: GeneratorKind::Letters,
+            seed: 0,
+            density: DEFAULT_DENSITY,
+            results: None,
+            workdir: std::env::temp_dir().join("matrix_transposer_test_all"),
+            tempdir: None,
+            atomic: false,
+            checkpoint: false,
+            resume: false,
+            progress: ProgressKind::Bar,
+            quiet: false,
+            estimate: false,
+            device: None,
         };
         _main(cli).unwrap();
     }
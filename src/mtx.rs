@@ -0,0 +1,817 @@
+use crate::cancel::Cancellation;
+use crate::cleanup::WorkFiles;
+use crate::generate::hash;
+use crate::progress::Progress;
+use crate::timing::{Phase, PhaseTimings};
+use crate::verify::fnv1a;
+use anyhow::{Context, Result, bail, ensure};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// How many sorted runs are merged at once, so the open files and their
+/// buffers stay bounded however many runs there are.
+const MAX_FAN_IN: usize = 256;
+/// The smallest read buffer of a run while merging.
+const MIN_RUN_BUFFER: usize = 2usize.pow(12);
+/// How many bytes of input go by between progress updates.
+const PROGRESS_STEP: u64 = 2u64.pow(20);
+
+/// What the values of a Matrix Market file are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Real,
+    Integer,
+    /// a real and an imaginary part
+    Complex,
+    /// no values, only where the nonzeros are
+    Pattern,
+}
+
+impl Field {
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Real => "real",
+            Field::Integer => "integer",
+            Field::Complex => "complex",
+            Field::Pattern => "pattern",
+        }
+    }
+
+    /// How many numbers follow the row and column of an entry.
+    fn values(self) -> usize {
+        match self {
+            Field::Real | Field::Integer => 1,
+            Field::Complex => 2,
+            Field::Pattern => 0,
+        }
+    }
+}
+
+/// Which entries of a square matrix a Matrix Market file leaves out, all
+/// but the lower triangle unless it's general.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symmetry {
+    General,
+    Symmetric,
+    SkewSymmetric,
+    Hermitian,
+}
+
+impl Symmetry {
+    pub fn name(self) -> &'static str {
+        match self {
+            Symmetry::General => "general",
+            Symmetry::Symmetric => "symmetric",
+            Symmetry::SkewSymmetric => "skew-symmetric",
+            Symmetry::Hermitian => "hermitian",
+        }
+    }
+}
+
+/// Everything of a Matrix Market coordinate file before its entries, see
+/// <https://math.nist.gov/MatrixMarket/formats.html>.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MtxHeader {
+    pub field: Field,
+    pub symmetry: Symmetry,
+    pub rows: u64,
+    pub cols: u64,
+    /// entries in the file, which leaves out the mirrored ones of symmetric
+    /// matrices
+    pub entries: u64,
+    /// the comment lines, `%` and all
+    pub comments: Vec<String>,
+}
+
+impl MtxHeader {
+    /// Read the banner, the comments and the size line, leaving `reader` at
+    /// the first entry. `line` is the line number `reader` is at, and is
+    /// kept up to date.
+    pub fn read(reader: &mut impl BufRead, line: &mut u64) -> Result<Self> {
+        let mut text = String::new();
+        *line += 1;
+        reader.read_line(&mut text)?;
+        let banner: Vec<String> = text.split_whitespace().map(str::to_lowercase).collect();
+        let [magic, object, format, field, symmetry] = banner.as_slice() else {
+            bail!("line {line}: expected a %%MatrixMarket banner with 4 words after it");
+        };
+        ensure!(
+            magic == "%%matrixmarket",
+            "line {line}: not a Matrix Market file"
+        );
+        ensure!(object == "matrix", "line {line}: {object} isn't a matrix");
+        ensure!(
+            format == "coordinate",
+            "line {line}: only coordinate files are sparse, not {format} ones"
+        );
+        let field = match field.as_str() {
+            "real" => Field::Real,
+            "integer" => Field::Integer,
+            "complex" => Field::Complex,
+            "pattern" => Field::Pattern,
+            _ => bail!("line {line}: unknown field {field}"),
+        };
+        let symmetry = match symmetry.as_str() {
+            "general" => Symmetry::General,
+            "symmetric" => Symmetry::Symmetric,
+            "skew-symmetric" => Symmetry::SkewSymmetric,
+            "hermitian" => Symmetry::Hermitian,
+            _ => bail!("line {line}: unknown symmetry {symmetry}"),
+        };
+        ensure!(
+            field != Field::Pattern || matches!(symmetry, Symmetry::General | Symmetry::Symmetric),
+            "line {line}: a pattern matrix can't be {}",
+            symmetry.name()
+        );
+
+        let mut comments = Vec::new();
+        let size = loop {
+            text.clear();
+            *line += 1;
+            ensure!(
+                reader.read_line(&mut text)? > 0,
+                "line {line}: the file ends before the size line"
+            );
+            if text.starts_with('%') {
+                comments.push(text.trim_end().to_string());
+            } else if !text.trim().is_empty() {
+                break text.trim();
+            }
+        };
+        let numbers = size
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .with_context(|| format!("line {line}: {size:?} isn't a size line"))?;
+        let [rows, cols, entries] = numbers[..] else {
+            bail!("line {line}: the size line needs rows, columns and entries");
+        };
+        ensure!(
+            symmetry == Symmetry::General || rows == cols,
+            "line {line}: a {} matrix has to be square, not {rows}x{cols}",
+            symmetry.name()
+        );
+        Ok(MtxHeader {
+            field,
+            symmetry,
+            rows,
+            cols,
+            entries,
+            comments,
+        })
+    }
+
+    /// The header of the transpose: rows and columns swap, and everything
+    /// else stays.
+    pub fn transposed(&self) -> Self {
+        MtxHeader {
+            rows: self.cols,
+            cols: self.rows,
+            ..self.clone()
+        }
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writeln!(
+            writer,
+            "%%MatrixMarket matrix coordinate {} {}",
+            self.field.name(),
+            self.symmetry.name()
+        )?;
+        for comment in &self.comments {
+            writeln!(writer, "{comment}")?;
+        }
+        writeln!(writer, "{} {} {}", self.rows, self.cols, self.entries)?;
+        Ok(())
+    }
+
+    /// The entry on `text`, line `line` of the file, with 0-based indices
+    /// and its values as they're written.
+    fn parse_entry(&self, text: &str, line: u64) -> Result<Entry> {
+        let mut words = text.split_whitespace();
+        let mut index = |name, len| -> Result<u64> {
+            let word = words
+                .next()
+                .with_context(|| format!("line {line}: an entry without a {name}"))?;
+            let index: u64 = word
+                .parse()
+                .with_context(|| format!("line {line}: {word:?} isn't a {name}"))?;
+            ensure!(
+                (1..=len).contains(&index),
+                "line {line}: {name} {index} is out of 1..={len}"
+            );
+            Ok(index - 1)
+        };
+        let row = index("row", self.rows)?;
+        let col = index("column", self.cols)?;
+        let values: Vec<&str> = words.collect();
+        ensure!(
+            values.len() == self.field.values(),
+            "line {line}: a {} entry needs {} values, not {}",
+            self.field.name(),
+            self.field.values(),
+            values.len()
+        );
+        match self.symmetry {
+            Symmetry::General => {}
+            Symmetry::SkewSymmetric => ensure!(
+                row > col,
+                "line {line}: a skew-symmetric matrix only stores entries below the diagonal"
+            ),
+            Symmetry::Symmetric | Symmetry::Hermitian => ensure!(
+                row >= col,
+                "line {line}: a {} matrix only stores entries on and below the diagonal",
+                self.symmetry.name()
+            ),
+        }
+        Ok(Entry {
+            row,
+            col,
+            value: values.join(" "),
+        })
+    }
+}
+
+/// An entry of a matrix, at 0-based indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    row: u64,
+    col: u64,
+    /// the values as they're written, separated by a space
+    value: String,
+}
+
+impl Entry {
+    /// The entry in the same place of the transpose of a `symmetry` matrix.
+    ///
+    /// Only general matrices move entries. The others stay in the lower
+    /// triangle, where the transpose of a symmetric matrix is the same, of a
+    /// skew-symmetric one it's negated and of a hermitian one conjugated.
+    fn transposed(self, symmetry: Symmetry) -> Self {
+        match symmetry {
+            Symmetry::General => Entry {
+                row: self.col,
+                col: self.row,
+                value: self.value,
+            },
+            Symmetry::Symmetric => self,
+            Symmetry::SkewSymmetric => Entry {
+                value: self
+                    .value
+                    .split(' ')
+                    .map(negate)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                ..self
+            },
+            Symmetry::Hermitian => {
+                let value = match self.value.split_once(' ') {
+                    Some((real, imaginary)) => format!("{real} {}", negate(imaginary)),
+                    None => self.value,
+                };
+                Entry { value, ..self }
+            }
+        }
+    }
+
+    /// Roughly what this takes in memory.
+    fn memory(&self) -> usize {
+        size_of::<Entry>() + self.value.len()
+    }
+
+    /// Write this as a record of a sorted run.
+    fn write_record(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&self.row.to_le_bytes())?;
+        writer.write_all(&self.col.to_le_bytes())?;
+        writer.write_all(&(self.value.len() as u32).to_le_bytes())?;
+        writer.write_all(self.value.as_bytes())?;
+        Ok(())
+    }
+
+    /// Read the next record of a sorted run, if there is one.
+    fn read_record(reader: &mut impl Read) -> Result<Option<Self>> {
+        let mut row = [0; 8];
+        match reader.read_exact(&mut row) {
+            Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let mut col = [0; 8];
+        let mut len = [0; 4];
+        reader.read_exact(&mut col)?;
+        reader.read_exact(&mut len)?;
+        let mut value = vec![0; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut value)?;
+        Ok(Some(Entry {
+            row: u64::from_le_bytes(row),
+            col: u64::from_le_bytes(col),
+            value: String::from_utf8(value)?,
+        }))
+    }
+
+    /// Write this as a line of a Matrix Market file.
+    fn write_line(&self, writer: &mut impl Write) -> Result<()> {
+        write!(writer, "{} {}", self.row + 1, self.col + 1)?;
+        if !self.value.is_empty() {
+            write!(writer, " {}", self.value)?;
+        }
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    /// A digest of this entry that doesn't depend on where it is in a file.
+    fn digest(&self) -> u64 {
+        let value = self.value.bytes().fold(0, fnv1a);
+        hash(hash(self.row, self.col), value)
+    }
+}
+
+/// A number as it's written, negated.
+fn negate(number: &str) -> String {
+    match number.strip_prefix('-') {
+        Some(positive) => positive.to_string(),
+        None => format!("-{}", number.strip_prefix('+').unwrap_or(number)),
+    }
+}
+
+/// Read the entries of a Matrix Market file, checking there are as many as
+/// `header` says.
+struct Entries<R> {
+    reader: R,
+    header: MtxHeader,
+    line: u64,
+    read: u64,
+    text: String,
+}
+
+impl<R: BufRead> Entries<R> {
+    fn new(mut reader: R) -> Result<Self> {
+        let mut line = 0;
+        let header = MtxHeader::read(&mut reader, &mut line)?;
+        Ok(Entries {
+            reader,
+            header,
+            line,
+            read: 0,
+            text: String::new(),
+        })
+    }
+
+    /// The next entry, and how many bytes it took up.
+    fn next_entry(&mut self) -> Result<Option<(Entry, u64)>> {
+        loop {
+            self.text.clear();
+            self.line += 1;
+            let len = self.reader.read_line(&mut self.text)?;
+            if len == 0 {
+                ensure!(
+                    self.read == self.header.entries,
+                    "the header says there are {} entries, but there are {}",
+                    self.header.entries,
+                    self.read
+                );
+                return Ok(None);
+            }
+            if self.text.trim().is_empty() {
+                continue;
+            }
+            ensure!(
+                self.read < self.header.entries,
+                "line {}: the header says there are only {} entries",
+                self.line,
+                self.header.entries
+            );
+            self.read += 1;
+            let entry = self.header.parse_entry(&self.text, self.line)?;
+            return Ok(Some((entry, len as u64)));
+        }
+    }
+}
+
+/// Transpose the Matrix Market coordinate file at `input` into `output`,
+/// with its entries in row-major order, by an external sort on their
+/// column and row.
+///
+/// At most `memory` bytes of entries are sorted in memory at once, and each
+/// sorted run goes to a file in `scratch_dir`. Those are then merged, at
+/// most [`MAX_FAN_IN`] at a time. Returns the header of the output.
+pub fn transpose(
+    input: &Path,
+    output: &Path,
+    scratch_dir: &Path,
+    memory: usize,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, MtxHeader, PhaseTimings)> {
+    ensure!(memory > 0, "there has to be some memory to sort in");
+    let mut timings = PhaseTimings::default();
+    // the runs go even with -k, they're only pieces of the output
+    let mut runs = WorkFiles::new(false);
+    let result = sort_into(
+        input,
+        output,
+        scratch_dir,
+        memory,
+        &mut runs,
+        &mut timings,
+        progress,
+        cancel,
+    );
+    let delete_result = timings.time(Phase::Cleanup, || {
+        let result = runs.remove_all();
+        // only goes if it's empty, and it doesn't matter if it stays
+        let _ = std::fs::remove_dir(scratch_dir);
+        result
+    });
+    let (output, header) = delete_result.and(result)?;
+    Ok((output, header, timings))
+}
+
+/// The fallible part of [`transpose`], split out so the runs get cleaned up
+/// in a timed phase no matter where this fails.
+#[allow(clippy::too_many_arguments)]
+fn sort_into(
+    input: &Path,
+    output: &Path,
+    scratch_dir: &Path,
+    memory: usize,
+    runs: &mut WorkFiles,
+    timings: &mut PhaseTimings,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, MtxHeader)> {
+    let (mut entries, input_len) = timings.time(Phase::Setup, || -> Result<_> {
+        create_dir_all(scratch_dir)
+            .with_context(|| format!("couldn't create {}", scratch_dir.display()))?;
+        let file =
+            File::open(input).with_context(|| format!("couldn't open {}", input.display()))?;
+        let input_len = file.metadata()?.len();
+        Ok((Entries::new(BufReader::new(file))?, input_len))
+    })?;
+    let header = entries.header.clone();
+    let context = || format!("can't transpose {}", input.display());
+
+    let mut run_paths = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_memory = 0;
+    let mut unreported = 0;
+    progress.start("sorting", header.entries, input_len);
+    loop {
+        let entry = timings
+            .time(Phase::Read, || entries.next_entry())
+            .with_context(context)?;
+        if let Some((entry, len)) = &entry {
+            chunk_memory += entry.memory();
+            unreported += len;
+            if unreported >= PROGRESS_STEP {
+                progress.advance(0, unreported);
+                unreported = 0;
+            }
+        }
+        let done = entry.is_none();
+        chunk.extend(entry.map(|(entry, _)| entry.transposed(header.symmetry)));
+        if chunk_memory < memory && !done {
+            continue;
+        }
+        cancel.check()?;
+        timings.time(Phase::Transpose, || {
+            chunk.sort_by_key(|entry: &Entry| (entry.row, entry.col))
+        });
+        // all of it fit, so it can go straight to the output
+        if done && run_paths.is_empty() {
+            progress.finish();
+            let output = timings.time(Phase::Write, || {
+                write_output(output, &header.transposed(), chunk.iter().map(Ok))
+            })?;
+            timings.time(Phase::Flush, || output.sync_all())?;
+            return Ok((output, header.transposed()));
+        }
+        if !chunk.is_empty() {
+            let path = runs
+                .track(scratch_dir.join(format!("run-{}", run_paths.len())))
+                .to_path_buf();
+            timings.time(Phase::Write, || -> Result<()> {
+                let mut writer = BufWriter::new(File::create(&path)?);
+                for entry in chunk.drain(..) {
+                    entry.write_record(&mut writer)?;
+                }
+                writer.flush()?;
+                Ok(())
+            })?;
+            run_paths.push(path);
+            chunk_memory = 0;
+        }
+        if done {
+            break;
+        }
+    }
+    progress.finish();
+
+    // merge runs into fewer, bigger ones until they can all be merged at once
+    let mut generation = 0;
+    while run_paths.len() > MAX_FAN_IN {
+        generation += 1;
+        let mut merged = Vec::new();
+        for (index, group) in run_paths.chunks(MAX_FAN_IN).enumerate() {
+            cancel.check()?;
+            let path = runs
+                .track(scratch_dir.join(format!("run-{generation}-{index}")))
+                .to_path_buf();
+            timings.time(Phase::Transpose, || -> Result<()> {
+                let mut writer = BufWriter::new(File::create(&path)?);
+                merge(group, memory, |entry| entry.write_record(&mut writer))?;
+                writer.flush()?;
+                Ok(())
+            })?;
+            for run in group {
+                runs.remove(run)?;
+            }
+            merged.push(path);
+        }
+        run_paths = merged;
+    }
+
+    cancel.check()?;
+    progress.start("merging", header.entries, input_len);
+    let output = timings.time(Phase::Write, || -> Result<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output)
+            .with_context(|| format!("couldn't create {}", output.display()))?;
+        let mut writer = BufWriter::new(file);
+        header.transposed().write(&mut writer)?;
+        let mut written = 0;
+        merge(&run_paths, memory, |entry| {
+            written += 1;
+            if written % 4096 == 0 {
+                progress.advance(4096, 0);
+            }
+            entry.write_line(&mut writer)
+        })?;
+        Ok(writer.into_inner()?)
+    })?;
+    progress.finish();
+    timings.time(Phase::Flush, || output.sync_all())?;
+    Ok((output, header.transposed()))
+}
+
+/// Write a Matrix Market file of `header` and `entries` to `path`, unsynced.
+fn write_output<'a>(
+    path: &Path,
+    header: &MtxHeader,
+    entries: impl Iterator<Item = Result<&'a Entry>>,
+) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .with_context(|| format!("couldn't create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    header.write(&mut writer)?;
+    for entry in entries {
+        entry?.write_line(&mut writer)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// Merge the sorted runs at `run_paths` into `emit`, in order, with about
+/// `memory` bytes of read buffers between them.
+fn merge(
+    run_paths: &[PathBuf],
+    memory: usize,
+    mut emit: impl FnMut(&Entry) -> Result<()>,
+) -> Result<()> {
+    let buffer = (memory / run_paths.len().max(1)).max(MIN_RUN_BUFFER);
+    let mut readers = run_paths
+        .iter()
+        .map(|path| Ok(BufReader::with_capacity(buffer, File::open(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut heads = Vec::with_capacity(readers.len());
+    let mut heap = BinaryHeap::new();
+    for (run, reader) in readers.iter_mut().enumerate() {
+        let head = Entry::read_record(reader)?;
+        if let Some(entry) = &head {
+            heap.push(Reverse((entry.row, entry.col, run)));
+        }
+        heads.push(head);
+    }
+    // ties go to the earlier run, which keeps duplicates in input order
+    while let Some(Reverse((_, _, run))) = heap.pop() {
+        let entry = heads[run].take().expect("every run in the heap has a head");
+        emit(&entry)?;
+        heads[run] = Entry::read_record(&mut readers[run])?;
+        if let Some(next) = &heads[run] {
+            heap.push(Reverse((next.row, next.col, run)));
+        }
+    }
+    Ok(())
+}
+
+/// The first thing wrong with a transposed Matrix Market file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MtxMismatch {
+    /// the header isn't the transposed one
+    Header {
+        expected: MtxHeader,
+        actual: MtxHeader,
+    },
+    /// the entry on `line` comes before the one above it
+    Unsorted { line: u64 },
+    /// the entries aren't those of the transpose
+    Entries,
+}
+
+impl fmt::Display for MtxMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MtxMismatch::Header { expected, actual } => write!(
+                f,
+                "the header is {}x{} with {} entries, but should be {}x{} with {}",
+                actual.rows,
+                actual.cols,
+                actual.entries,
+                expected.rows,
+                expected.cols,
+                expected.entries
+            ),
+            MtxMismatch::Unsorted { line } => {
+                write!(f, "the entry on line {line} is out of order")
+            }
+            MtxMismatch::Entries => write!(f, "the entries aren't those of the transpose"),
+        }
+    }
+}
+
+/// Check that `output` is the transpose of the Matrix Market file at
+/// `input`, in row-major order, in one sequential pass over each.
+///
+/// Entries are compared by a sum of their digests, which doesn't depend on
+/// their order and holds nothing but the sum in memory.
+pub fn verify_transposed(input: &Path, output: &Path) -> Result<Option<MtxMismatch>> {
+    let mut entries = Entries::new(BufReader::new(File::open(input)?))?;
+    let symmetry = entries.header.symmetry;
+    let mut expected = 0u64;
+    while let Some((entry, _)) = entries.next_entry()? {
+        expected = expected.wrapping_add(entry.transposed(symmetry).digest());
+    }
+    let expected_header = entries.header.transposed();
+
+    let mut entries = Entries::new(BufReader::new(File::open(output)?))?;
+    if entries.header != expected_header {
+        return Ok(Some(MtxMismatch::Header {
+            expected: expected_header,
+            actual: entries.header,
+        }));
+    }
+    let mut actual = 0u64;
+    let mut previous = None;
+    while let Some((entry, _)) = entries.next_entry()? {
+        if previous.is_some_and(|previous| previous > (entry.row, entry.col)) {
+            return Ok(Some(MtxMismatch::Unsorted { line: entries.line }));
+        }
+        previous = Some((entry.row, entry.col));
+        actual = actual.wrapping_add(entry.digest());
+    }
+    Ok((actual != expected).then_some(MtxMismatch::Entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Silent;
+
+    const GENERAL: &str = "%%MatrixMarket matrix coordinate real general\n\
+                           % a comment\n\
+                           3 4 5\n\
+                           1 1 1.5\n\
+                           1 4 -2\n\
+                           3 2 3e1\n\
+                           2 1 4\n\
+                           3 4 5\n";
+
+    /// Transpose `text` with room for `memory` bytes of entries at a time,
+    /// in a directory of its own for every `test`, so tests can run at once.
+    fn transposed(test: &str, text: &str, memory: usize) -> Result<String> {
+        let dir = std::env::temp_dir().join(format!("matrix_transposer_mtx_{test}"));
+        create_dir_all(&dir)?;
+        let (input, output) = (dir.join("input.mtx"), dir.join("output.mtx"));
+        std::fs::write(&input, text)?;
+        let result = transpose(
+            &input,
+            &output,
+            &dir.join("runs"),
+            memory,
+            &Silent,
+            &Cancellation::new(),
+        );
+        let checked = result.and_then(|_| verify_transposed(&input, &output));
+        let output_text = std::fs::read_to_string(&output);
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(checked?, None);
+        Ok(output_text?)
+    }
+
+    #[test]
+    fn transposes_general_matrices_in_runs() {
+        let expected = "%%MatrixMarket matrix coordinate real general\n\
+                        % a comment\n\
+                        4 3 5\n\
+                        1 1 1.5\n\
+                        1 2 4\n\
+                        2 3 3e1\n\
+                        4 1 -2\n\
+                        4 3 5\n";
+        // all in memory, and one entry per run
+        assert_eq!(transposed("general", GENERAL, 1 << 20).unwrap(), expected);
+        assert_eq!(transposed("general", GENERAL, 1).unwrap(), expected);
+
+        // more runs than are merged at once
+        let entries = 3 * MAX_FAN_IN as u64;
+        let mut big =
+            format!("%%MatrixMarket matrix coordinate integer general\n50 40 {entries}\n");
+        for index in 0..entries {
+            let (row, col) = (hash(0, index) % 50, hash(1, index) % 40);
+            big += &format!("{} {} {index}\n", row + 1, col + 1);
+        }
+        transposed("general", &big, 1).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_lower_triangle_of_symmetric_matrices() {
+        let skew = "%%MatrixMarket matrix coordinate integer skew-symmetric\n\
+                    3 3 2\n\
+                    3 1 -7\n\
+                    2 1 4\n";
+        assert_eq!(
+            transposed("symmetric", skew, 1 << 20).unwrap(),
+            "%%MatrixMarket matrix coordinate integer skew-symmetric\n3 3 2\n2 1 -4\n3 1 7\n"
+        );
+        let hermitian = "%%MatrixMarket matrix coordinate complex hermitian\n\
+                         2 2 2\n\
+                         1 1 2 0\n\
+                         2 1 1 -1.5\n";
+        assert_eq!(
+            transposed("symmetric", hermitian, 1 << 20).unwrap(),
+            "%%MatrixMarket matrix coordinate complex hermitian\n2 2 2\n1 1 2 -0\n2 1 1 1.5\n"
+        );
+        let pattern = "%%MatrixMarket matrix coordinate pattern general\n2 3 2\n1 3\n2 1\n";
+        assert_eq!(
+            transposed("symmetric", pattern, 1 << 20).unwrap(),
+            "%%MatrixMarket matrix coordinate pattern general\n3 2 2\n1 2\n3 1\n"
+        );
+    }
+
+    #[test]
+    fn errors_say_which_line() {
+        let error = |text: &str| format!("{:#}", transposed("errors", text, 1 << 20).unwrap_err());
+        assert!(
+            error(&GENERAL.replace("3 2 3e1", "3 5 3e1"))
+                .ends_with("line 6: column 5 is out of 1..=4")
+        );
+        assert!(
+            error(&GENERAL.replace("2 1 4", "2 1"))
+                .ends_with("line 7: a real entry needs 1 values, not 0")
+        );
+        assert!(
+            error(&GENERAL.replace("3 4 5\n1", "3 4 4\n1"))
+                .ends_with("line 8: the header says there are only 4 entries")
+        );
+        assert!(
+            error("%%MatrixMarket matrix array real general\n")
+                .ends_with("line 1: only coordinate files are sparse, not array ones")
+        );
+        let upper = "%%MatrixMarket matrix coordinate real symmetric\n2 2 1\n1 2 1\n";
+        assert!(
+            error(upper).ends_with(
+                "line 3: a symmetric matrix only stores entries on and below the diagonal"
+            )
+        );
+    }
+
+    #[test]
+    fn catches_wrong_transposes() {
+        let dir = std::env::temp_dir().join("matrix_transposer_mtx_verify");
+        create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("input.mtx"), dir.join("output.mtx"));
+        std::fs::write(&input, GENERAL).unwrap();
+        let check = |text: &str| {
+            std::fs::write(&output, text).unwrap();
+            verify_transposed(&input, &output).unwrap()
+        };
+        assert!(matches!(check(GENERAL), Some(MtxMismatch::Header { .. })));
+        let header = "%%MatrixMarket matrix coordinate real general\n% a comment\n4 3 5\n";
+        let unsorted = format!("{header}1 2 4\n1 1 1.5\n2 3 3e1\n4 1 -2\n4 3 5\n");
+        assert_eq!(check(&unsorted), Some(MtxMismatch::Unsorted { line: 5 }));
+        let wrong_value = format!("{header}1 1 1.5\n1 2 4\n2 3 3e1\n4 1 -2\n4 3 6\n");
+        assert_eq!(check(&wrong_value), Some(MtxMismatch::Entries));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
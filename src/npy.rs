@@ -0,0 +1,419 @@
+use crate::Dimensions;
+use crate::axes::Axes;
+use anyhow::{Context, Result, bail, ensure};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+/// The magic, the version and a `u16` header length.
+const V1_PREFIX_LEN: usize = MAGIC.len() + 2 + 2;
+/// The magic, the version and a `u32` header length.
+const V2_PREFIX_LEN: usize = MAGIC.len() + 2 + 4;
+/// What the whole header is padded to a multiple of, so the payload is
+/// aligned.
+const ALIGNMENT: usize = 64;
+
+/// The header of a NumPy `.npy` file, see
+/// <https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html>.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpyHeader {
+    /// the dtype, e.g. `<f8`
+    pub descr: String,
+    /// whether the payload is column-major
+    pub fortran_order: bool,
+    pub shape: Vec<usize>,
+}
+
+impl NpyHeader {
+    /// Whether the file at `path` starts like a `.npy` file.
+    pub fn is_npy(path: &Path) -> bool {
+        let mut magic = [0; MAGIC.len()];
+        File::open(path)
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok_and(|()| magic == *MAGIC)
+    }
+
+    /// The header of the `.npy` file at `path`, and where its payload starts.
+    pub fn of_file(path: &Path) -> Result<(Self, u64)> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (header, payload_offset) = NpyHeader::read(&mut reader)
+            .with_context(|| format!("{} isn't a usable .npy file", path.display()))?;
+        let payload_len = path.metadata()?.len() - payload_offset;
+        let expected = header.payload_len()?;
+        ensure!(
+            payload_len == expected,
+            "{} should have {expected} bytes of data after its header, but has {payload_len}",
+            path.display()
+        );
+        Ok((header, payload_offset))
+    }
+
+    /// Parse a header, returning it with its length, which is where the
+    /// payload starts.
+    pub fn read(reader: &mut impl Read) -> Result<(Self, u64)> {
+        let mut magic = [0; MAGIC.len() + 2];
+        reader.read_exact(&mut magic)?;
+        ensure!(
+            magic.starts_with(MAGIC),
+            "it doesn't start with the .npy magic"
+        );
+        let (prefix_len, dict_len) = match magic[MAGIC.len()] {
+            1 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len)?;
+                (V1_PREFIX_LEN, u16::from_le_bytes(len) as usize)
+            }
+            2 | 3 => {
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                (V2_PREFIX_LEN, u32::from_le_bytes(len) as usize)
+            }
+            version => bail!("it's .npy version {version}, which isn't supported"),
+        };
+        let mut dict = vec![0; dict_len];
+        reader.read_exact(&mut dict)?;
+        let dict = String::from_utf8(dict).context("its header isn't text")?;
+        Ok((parse_dict(&dict)?, (prefix_len + dict_len) as u64))
+    }
+
+    /// Bytes per element, going by the dtype.
+    pub fn elem_size(&self) -> Result<usize> {
+        let descr = self.descr.trim_start_matches(['<', '>', '|', '=']);
+        let mut chars = descr.chars();
+        let kind = chars.next().context("the dtype is empty")?;
+        let digits: String = chars.take_while(char::is_ascii_digit).collect();
+        ensure!(
+            kind != 'O',
+            "object arrays hold pointers, so they can't be transposed on disk"
+        );
+        let count: usize = digits
+            .parse()
+            .with_context(|| format!("the dtype {:?} has no size", self.descr))?;
+        // unicode strings are counted in UCS-4 characters
+        let elem_size = if kind == 'U' { count * 4 } else { count };
+        ensure!(elem_size > 0, "the dtype {:?} is empty", self.descr);
+        Ok(elem_size)
+    }
+
+    /// How many elements the array has.
+    pub fn elements(&self) -> Result<u64> {
+        self.shape
+            .iter()
+            .try_fold(1u64, |elements, &len| elements.checked_mul(len as u64))
+            .with_context(|| format!("shape {:?} has too many elements", self.shape))
+    }
+
+    /// How many bytes of data should follow the header.
+    pub fn payload_len(&self) -> Result<u64> {
+        self.elements()?
+            .checked_mul(self.elem_size()? as u64)
+            .with_context(|| format!("shape {:?} of {:?} is too big", self.shape, self.descr))
+    }
+
+    /// The matrix the payload holds, as it's laid out: a column-major
+    /// array is the row-major matrix of its transpose.
+    pub fn dims(&self) -> Result<Dimensions> {
+        let &[rows, cols] = self.shape.as_slice() else {
+            bail!(
+                "only 2-d arrays can be transposed, not shape {:?}",
+                self.shape
+            );
+        };
+        let (rows, cols) = if self.fortran_order {
+            (cols, rows)
+        } else {
+            (rows, cols)
+        };
+        Ok(Dimensions::new(rows, cols).with_elem_size(self.elem_size()?))
+    }
+
+    /// What permuting the array's axes by `permutation` does to the payload,
+    /// keeping it in the same order.
+    pub fn permuted_dims(&self, permutation: &[usize]) -> Result<Dimensions> {
+        let elem_size = self.elem_size()?;
+        if !self.fortran_order {
+            return Dimensions::permuted(&self.shape, permutation, elem_size);
+        }
+        // a column-major array is the row-major array of its reversed axes
+        let ndim = Axes::new(&self.shape, permutation)?.shape().len();
+        let shape: Vec<usize> = self.shape.iter().rev().copied().collect();
+        let permutation: Vec<usize> = permutation
+            .iter()
+            .rev()
+            .map(|axis| ndim - 1 - axis)
+            .collect();
+        Dimensions::permuted(&shape, &permutation, elem_size)
+    }
+
+    /// The header of the transpose once the payload is transposed: the
+    /// shape reversed, in the same order.
+    pub fn transposed(&self) -> Self {
+        self.permuted(&Axes::reversed(self.shape.len()))
+    }
+
+    /// The header once the payload's axes are [permuted](Self::permuted_dims)
+    /// by `permutation`.
+    pub fn permuted(&self, permutation: &[usize]) -> Self {
+        NpyHeader {
+            shape: permutation.iter().map(|&axis| self.shape[axis]).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// The header of the transpose of the same payload: the shape reversed,
+    /// in the other order.
+    pub fn flipped(&self) -> Self {
+        NpyHeader {
+            fortran_order: !self.fortran_order,
+            ..self.transposed()
+        }
+    }
+
+    fn dict(&self) -> String {
+        let shape = match self.shape.as_slice() {
+            [len] => format!("({len},)"),
+            shape => format!(
+                "({})",
+                shape
+                    .iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let fortran_order = if self.fortran_order { "True" } else { "False" };
+        format!(
+            "{{'descr': '{}', 'fortran_order': {fortran_order}, 'shape': {shape}, }}",
+            self.descr
+        )
+    }
+
+    /// The header as the shortest version 1 (or, if it's too long for that,
+    /// version 2) header padded to a whole number of [`ALIGNMENT`]s.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // the dict ends with a newline
+        let len =
+            |prefix_len: usize| (prefix_len + self.dict().len() + 1).next_multiple_of(ALIGNMENT);
+        self.to_bytes_of_len(len(V1_PREFIX_LEN), false)
+            .or_else(|| self.to_bytes_of_len(len(V2_PREFIX_LEN), true))
+            .expect("a version 2 header fits any dict")
+    }
+
+    /// The header padded to exactly `len` bytes, if it fits.
+    fn to_bytes_of_len(&self, len: usize, v2: bool) -> Option<Vec<u8>> {
+        let dict = self.dict();
+        let prefix_len = if v2 { V2_PREFIX_LEN } else { V1_PREFIX_LEN };
+        let padding = len.checked_sub(prefix_len + dict.len() + 1)?;
+        let mut bytes = Vec::with_capacity(len);
+        bytes.extend_from_slice(MAGIC);
+        let dict_len = len - prefix_len;
+        if v2 {
+            bytes.extend_from_slice(&[2, 0]);
+            bytes.extend_from_slice(&u32::try_from(dict_len).ok()?.to_le_bytes());
+        } else {
+            bytes.extend_from_slice(&[1, 0]);
+            bytes.extend_from_slice(&u16::try_from(dict_len).ok()?.to_le_bytes());
+        }
+        bytes.extend_from_slice(dict.as_bytes());
+        bytes.resize(len - 1, b' ');
+        bytes.push(b'\n');
+        debug_assert_eq!(bytes.len(), prefix_len + dict.len() + padding + 1);
+        Some(bytes)
+    }
+}
+
+/// Parse the dict literal of a header, e.g.
+/// `{'descr': '<f8', 'fortran_order': False, 'shape': (3, 4), }`.
+fn parse_dict(dict: &str) -> Result<NpyHeader> {
+    let value = |key: &str| -> Result<&str> {
+        let start = dict
+            .find(&format!("'{key}'"))
+            .or_else(|| dict.find(&format!("\"{key}\"")))
+            .with_context(|| format!("its header has no {key}"))?;
+        let rest = dict[start + key.len() + 2..].trim_start();
+        let rest = rest
+            .strip_prefix(':')
+            .with_context(|| format!("its header has no value for {key}"))?;
+        Ok(rest.trim_start())
+    };
+
+    let descr = value("descr")?;
+    let quote = descr
+        .chars()
+        .next()
+        .filter(|quote| matches!(quote, '\'' | '"'));
+    let Some(quote) = quote else {
+        bail!("its dtype is structured, only plain dtypes are supported");
+    };
+    let descr = &descr[1..];
+    let descr = &descr[..descr.find(quote).context("its dtype isn't terminated")?];
+
+    let fortran_order = match value("fortran_order")? {
+        rest if rest.starts_with("True") => true,
+        rest if rest.starts_with("False") => false,
+        _ => bail!("its fortran_order isn't True or False"),
+    };
+
+    let shape = value("shape")?
+        .strip_prefix('(')
+        .context("its shape isn't a tuple")?;
+    let shape = &shape[..shape.find(')').context("its shape isn't terminated")?];
+    let shape = shape
+        .split(',')
+        .map(str::trim)
+        .filter(|len| !len.is_empty())
+        // Python 2 wrote longs with an L
+        .map(|len| len.trim_end_matches('L').parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()
+        .context("its shape isn't a tuple of lengths")?;
+
+    Ok(NpyHeader {
+        descr: descr.to_string(),
+        fortran_order,
+        shape,
+    })
+}
+
+/// Write a `.npy` file at `path` with `header` followed by all of `payload`.
+pub fn write_npy(header: &NpyHeader, payload: &mut File, path: &Path) -> Result<File> {
+    let mut output = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .with_context(|| format!("couldn't create {}", path.display()))?;
+    output.write_all(&header.to_bytes())?;
+    payload.seek(SeekFrom::Start(0))?;
+    std::io::copy(payload, &mut output)?;
+    output.sync_all()?;
+    Ok(output)
+}
+
+/// Transpose the `.npy` file at `input` without touching its payload, by
+/// [flipping](NpyHeader::flipped) its header, into `output` or, without
+/// one, in place. Returns the new header.
+///
+/// In place only the header is rewritten, which works as long as the new
+/// one fits where the old one was, which it almost always does because of
+/// the padding.
+pub fn flip(input: &Path, output: Option<&Path>) -> Result<NpyHeader> {
+    let (header, payload_offset) = NpyHeader::of_file(input)?;
+    let flipped = header.flipped();
+    match output {
+        Some(output) => {
+            let mut payload = File::open(input)?;
+            payload.seek(SeekFrom::Start(payload_offset))?;
+            let mut output_file = File::create(output)
+                .with_context(|| format!("couldn't create {}", output.display()))?;
+            output_file.write_all(&flipped.to_bytes())?;
+            std::io::copy(&mut payload, &mut output_file)?;
+            output_file.sync_all()?;
+        }
+        None => {
+            let len = payload_offset as usize;
+            let bytes = flipped
+                .to_bytes_of_len(len, false)
+                .or_else(|| flipped.to_bytes_of_len(len, true))
+                .with_context(|| {
+                    format!(
+                        "the flipped header doesn't fit in place in {}, use --output",
+                        input.display()
+                    )
+                })?;
+            let file = OpenOptions::new().write(true).open(input)?;
+            std::os::unix::fs::FileExt::write_all_at(&file, &bytes, 0)?;
+            file.sync_all()?;
+        }
+    }
+    Ok(flipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `np.save` writes for `np.zeros((3, 5), dtype='<f4')`.
+    fn numpy_header() -> Vec<u8> {
+        let mut bytes = b"\x93NUMPY\x01\x00\x76\x00".to_vec();
+        bytes.extend_from_slice(b"{'descr': '<f4', 'fortran_order': False, 'shape': (3, 5), }");
+        bytes.resize(127, b' ');
+        bytes.push(b'\n');
+        bytes
+    }
+
+    #[test]
+    fn reads_what_numpy_writes() {
+        let (header, payload_offset) = NpyHeader::read(&mut numpy_header().as_slice()).unwrap();
+        assert_eq!(payload_offset, 128);
+        assert_eq!(
+            header,
+            NpyHeader {
+                descr: "<f4".to_string(),
+                fortran_order: false,
+                shape: vec![3, 5],
+            }
+        );
+        assert_eq!(
+            header.dims().unwrap(),
+            Dimensions::new(3, 5).with_elem_size(4)
+        );
+        assert_eq!(header.to_bytes(), numpy_header());
+    }
+
+    #[test]
+    fn round_trips_and_flips() {
+        let header = NpyHeader {
+            descr: "<U3".to_string(),
+            fortran_order: true,
+            shape: vec![7, 2],
+        };
+        assert_eq!(header.elem_size().unwrap(), 12);
+        assert_eq!(
+            header.dims().unwrap(),
+            Dimensions::new(2, 7).with_elem_size(12)
+        );
+        let bytes = header.to_bytes();
+        assert!(bytes.len().is_multiple_of(ALIGNMENT));
+        let (parsed, payload_offset) = NpyHeader::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(
+            (parsed, payload_offset),
+            (header.clone(), bytes.len() as u64)
+        );
+
+        let flipped = header.flipped();
+        assert_eq!(flipped.shape, [2, 7]);
+        assert!(!flipped.fortran_order);
+        // the same payload, read the same way
+        assert_eq!(flipped.dims().unwrap(), header.dims().unwrap());
+        assert_eq!(
+            header.transposed().dims().unwrap(),
+            Dimensions::new(7, 2).with_elem_size(12)
+        );
+    }
+
+    #[test]
+    fn rejects_what_it_cant_transpose() {
+        let header = |descr: &str, shape: Vec<usize>| NpyHeader {
+            descr: descr.to_string(),
+            fortran_order: false,
+            shape,
+        };
+        assert!(header("|O", vec![2, 2]).dims().is_err());
+        assert!(header("<f8", vec![2, 2, 2]).dims().is_err());
+        assert!(
+            parse_dict("{'descr': [('a', '<i4')], 'fortran_order': False, 'shape': (2,), }")
+                .is_err()
+        );
+        let huge = header("<f8", vec![1 << 31, 1 << 30]);
+        assert!(huge.elements().is_ok() && huge.payload_len().is_err());
+        assert!(header("|u1", vec![usize::MAX, 3]).elements().is_err());
+        assert_eq!(
+            parse_dict("{'descr': '|u1', 'fortran_order': False, 'shape': (4L, 3L), }")
+                .unwrap()
+                .shape,
+            [4, 3]
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use crate::Dimensions;
+use crate::axes::{Axes, Walk};
+use crate::cancel::Cancellation;
+use crate::progress::Progress;
+use crate::strategies::Tiling;
+use crate::timing::{Phase, PhaseTimings};
+use anyhow::{Result, ensure};
+use memmap::{MmapMut, MmapOptions};
+use rayon::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::prelude::FileExt;
+use std::path::Path;
+
+/// Elements per side of the blocks the in-memory permutes copy the two
+/// innermost axes in, so both the reads and the writes of a block stay
+/// within a few pages.
+const BLOCK: usize = 64;
+
+/// The axes of a permute: the innermost one of the input, which is read
+/// contiguously, the innermost one of the output, which is written
+/// contiguously, and all the others.
+struct Inner {
+    outer: Vec<Walk>,
+    input: Walk,
+    output: Walk,
+    /// whether both are the same axis, so whole lines stay together
+    same: bool,
+}
+
+impl Inner {
+    fn of(axes: &Axes) -> Self {
+        let walks = axes.walks();
+        let input_axis = walks.len() - 1;
+        let output_axis = axes.permutation()[input_axis];
+        Inner {
+            outer: (0..walks.len())
+                .filter(|axis| *axis != input_axis && *axis != output_axis)
+                .map(|axis| walks[axis])
+                .collect(),
+            input: walks[input_axis],
+            output: walks[output_axis],
+            same: input_axis == output_axis,
+        }
+    }
+
+    /// Where each combination of the outer axes starts in the input and in
+    /// the output, in elements and in input order.
+    fn bases(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let count: u64 = self.outer.iter().map(|walk| walk.len as u64).product();
+        (0..count).map(|mut index| {
+            let (mut input, mut output) = (0, 0);
+            for walk in self.outer.iter().rev() {
+                let position = index % walk.len as u64;
+                index /= walk.len as u64;
+                input += position * walk.input_stride;
+                output += position * walk.output_stride;
+            }
+            (input, output)
+        })
+    }
+
+    /// The input lines under each of the [`bases`](Self::bases).
+    fn lines_per_base(&self) -> usize {
+        if self.same { 1 } else { self.output.len }
+    }
+}
+
+/// Permute `input` into `output`, a block of the two innermost axes at a
+/// time.
+fn permute_slice(
+    axes: &Axes,
+    elem_size: usize,
+    input: &[u8],
+    output: &mut [u8],
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<()> {
+    let inner = Inner::of(axes);
+    let mut copy = |from: u64, to: u64, len: usize| {
+        let (from, to, len) = (
+            from as usize * elem_size,
+            to as usize * elem_size,
+            len * elem_size,
+        );
+        output[to..to + len].copy_from_slice(&input[from..from + len]);
+    };
+    let line_len = (inner.input.len * elem_size) as u64;
+    for (input_base, output_base) in inner.bases() {
+        cancel.check()?;
+        if inner.same {
+            copy(input_base, output_base, inner.input.len);
+        } else {
+            for first_y in (0..inner.output.len).step_by(BLOCK) {
+                for first_x in (0..inner.input.len).step_by(BLOCK) {
+                    for y in first_y..inner.output.len.min(first_y + BLOCK) {
+                        for x in first_x..inner.input.len.min(first_x + BLOCK) {
+                            let (x, y) = (x as u64, y as u64);
+                            copy(
+                                input_base
+                                    + y * inner.output.input_stride
+                                    + x * inner.input.input_stride,
+                                output_base
+                                    + y * inner.output.output_stride
+                                    + x * inner.input.output_stride,
+                                1,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        let lines = inner.lines_per_base() as u64;
+        progress.advance(lines, lines * line_len);
+    }
+    Ok(())
+}
+
+/// Like [`in_memory`](crate::strategies::in_memory), for `dims` that are
+/// [permuted](Dimensions::permuted) by `axes`.
+pub fn in_memory(
+    dims @ Dimensions {
+        size,
+        rows,
+        elem_size,
+        ..
+    }: Dimensions,
+    axes: &Axes,
+    input_path: &Path,
+    output_path: &Path,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, PhaseTimings)> {
+    let mut timings = PhaseTimings::default();
+    let (mut input_file, mut output_file) = timings.time(Phase::Setup, || -> Result<_> {
+        let input_file = dims.open_input(input_path)?;
+        let output_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output_path)?;
+        Ok((input_file, output_file))
+    })?;
+
+    let mut input_buff = Vec::with_capacity(size as usize);
+    let num_read_bytes = timings.time(Phase::Read, || input_file.read_to_end(&mut input_buff))?;
+    ensure!(
+        num_read_bytes == size as usize,
+        "the input is {num_read_bytes} bytes, not {size}"
+    );
+
+    progress.start("permute", rows as u64, size);
+    let output_buff = timings.time(Phase::Transpose, || -> Result<_> {
+        let mut output_buff = vec![0; size as usize];
+        permute_slice(
+            axes,
+            elem_size,
+            &input_buff,
+            &mut output_buff,
+            progress,
+            cancel,
+        )?;
+        Ok(output_buff)
+    })?;
+    progress.finish();
+
+    timings.time(Phase::Write, || output_file.write_all(&output_buff))?;
+    timings.time(Phase::Flush, || {
+        output_file.flush()?;
+        output_file.sync_all()
+    })?;
+
+    Ok((output_file, timings))
+}
+
+/// Like [`mmap_solution`](crate::strategies::mmap_solution), for `dims`
+/// that are [permuted](Dimensions::permuted) by `axes`.
+pub fn mmap(
+    dims @ Dimensions {
+        size,
+        rows,
+        elem_size,
+        ..
+    }: Dimensions,
+    axes: &Axes,
+    input_path: &Path,
+    output_path: &Path,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, PhaseTimings)> {
+    let mut timings = PhaseTimings::default();
+    let (output_file, input_mmap, mut output_mmap) =
+        timings.time(Phase::Setup, || -> Result<_> {
+            let input_file = File::open(input_path)?;
+            let output_file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output_path)?;
+            output_file.set_len(size)?;
+            let input_mmap = unsafe {
+                MmapOptions::new()
+                    .offset(dims.input_offset)
+                    .map(&input_file)?
+            };
+            let output_mmap = unsafe { MmapMut::map_mut(&output_file)? };
+            Ok((output_file, input_mmap, output_mmap))
+        })?;
+    ensure!(
+        input_mmap.len() as u64 == size,
+        "the input is {} bytes, not {size}",
+        input_mmap.len()
+    );
+
+    progress.start("permute", rows as u64, size);
+    timings.time(Phase::Transpose, || {
+        permute_slice(
+            axes,
+            elem_size,
+            &input_mmap,
+            &mut output_mmap,
+            progress,
+            cancel,
+        )
+    })?;
+    progress.finish();
+
+    timings.time(Phase::Flush, || {
+        output_mmap.flush()?;
+        output_file.sync_all()
+    })?;
+
+    Ok((output_file, timings))
+}
+
+/// Like [`buffered_disk_io_solution`](crate::strategies::buffered_disk_io_solution),
+/// for `dims` that are [permuted](Dimensions::permuted) by `axes`.
+///
+/// For every combination of the outer axes, the input's innermost axis is
+/// the tile's columns, `pass_cols` of them per pass, and the output's
+/// innermost axis its rows, `band_rows` of them per band. Each column of a
+/// band is one contiguous write.
+#[cfg(unix)]
+pub fn buffered_on_disk(
+    Dimensions {
+        size,
+        rows,
+        elem_size,
+        input_offset,
+        ..
+    }: Dimensions,
+    axes: &Axes,
+    input_path: &Path,
+    output_path: &Path,
+    tiling: Tiling,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, PhaseTimings)> {
+    let inner = Inner::of(axes);
+    let Tiling { band_rows, .. } = tiling;
+    let pass_cols = tiling.pass_cols(inner.input.len);
+    ensure!(
+        band_rows > 0 && pass_cols > 0,
+        "tiles need at least one row and column"
+    );
+    let mut timings = PhaseTimings::default();
+    let (input_file, mut output_file) = timings.time(Phase::Setup, || -> Result<_> {
+        let input_file = File::open(input_path)?;
+        let output_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output_path)?;
+        output_file.set_len(size)?;
+        Ok((input_file, output_file))
+    })?;
+
+    let elem = elem_size as u64;
+    let mut input_line_buff = vec![0; pass_cols * elem_size];
+    let mut output_buff_buff: Vec<Vec<u8>> =
+        vec![Vec::with_capacity(band_rows * elem_size); pass_cols];
+    progress.start(
+        "permute",
+        (rows * tiling.passes(inner.input.len)) as u64,
+        size,
+    );
+
+    for (input_base, output_base) in inner.bases() {
+        for first_x in (0..inner.input.len).step_by(pass_cols) {
+            let pass_len = pass_cols.min(inner.input.len - first_x);
+            let input_line_buff = &mut input_line_buff[..pass_len * elem_size];
+            let output_buff_buff = &mut output_buff_buff[..pass_len];
+            let (input_start, output_start) = (
+                input_base + first_x as u64 * inner.input.input_stride,
+                output_base + first_x as u64 * inner.input.output_stride,
+            );
+            if inner.same {
+                // the innermost axis doesn't move, so lines are copied as is
+                cancel.check()?;
+                timings.time(Phase::Read, || {
+                    input_file.read_exact_at(input_line_buff, input_offset + input_start * elem)
+                })?;
+                timings.time(Phase::Write, || {
+                    output_file.write_all_at(input_line_buff, output_start * elem)
+                })?;
+                progress.advance(1, input_line_buff.len() as u64);
+                continue;
+            }
+            for first_y in (0..inner.output.len).step_by(band_rows) {
+                let band_len = band_rows.min(inner.output.len - first_y);
+                for y in first_y..first_y + band_len {
+                    cancel.check()?;
+                    timings.time(Phase::Read, || {
+                        input_file.read_exact_at(
+                            input_line_buff,
+                            input_offset
+                                + (input_start + y as u64 * inner.output.input_stride) * elem,
+                        )
+                    })?;
+                    timings.time(Phase::Transpose, || {
+                        output_buff_buff
+                            .par_iter_mut()
+                            .zip(input_line_buff.par_chunks_exact(elem_size))
+                            .for_each(|(col_buf, element)| col_buf.extend_from_slice(element))
+                    });
+                    progress.advance(1, input_line_buff.len() as u64);
+                }
+                let band_start = output_start + first_y as u64 * inner.output.output_stride;
+                timings.time(Phase::Write, || {
+                    output_buff_buff
+                        .par_iter()
+                        .enumerate()
+                        .try_for_each(|(x, col_buf)| {
+                            output_file.write_all_at(
+                                col_buf,
+                                (band_start + x as u64 * inner.input.output_stride) * elem,
+                            )
+                        })
+                })?;
+                output_buff_buff
+                    .par_iter_mut()
+                    .for_each(|col_buf| col_buf.clear());
+            }
+        }
+    }
+    progress.finish();
+
+    timings.time(Phase::Flush, || {
+        output_file.flush()?;
+        output_file.sync_all()
+    })?;
+
+    Ok((output_file, timings))
+}
@@ -0,0 +1,282 @@
+use crate::checkpoint::Checkpoints;
+use crate::cost::DeviceProfile;
+use crate::strategies::Tiling;
+use crate::workspace::Workspace;
+use crate::{Dimensions, Strategy};
+use anyhow::{Context, Result, ensure};
+use size::Size;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// The share of the available memory a plan uses, leaving the rest to the
+/// page cache and everything else running.
+const MEMORY_SHARE: f64 = 0.75;
+/// The smallest write worth making, a page, unless a device profile says
+/// it takes bigger ones.
+const MIN_WRITE: usize = 2usize.pow(12);
+/// Writes bigger than this hardly get any faster.
+const MAX_WRITE: usize = 2usize.pow(20);
+
+/// What the machine has to offer a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resources {
+    /// bytes of memory that can be allocated without swapping
+    pub available_memory: u64,
+    /// bytes free for outputs in the workspace's directory
+    pub free_disk: u64,
+}
+
+impl Resources {
+    /// What's available right now, going by `/proc/meminfo` and `statvfs`
+    /// of the workspace's directory.
+    pub fn of_system(workspace: &Workspace) -> Result<Self> {
+        let meminfo =
+            std::fs::read_to_string("/proc/meminfo").context("couldn't read /proc/meminfo")?;
+        Ok(Resources {
+            available_memory: mem_available(&meminfo)
+                .context("/proc/meminfo doesn't say how much memory is available")?,
+            free_disk: free_space(&workspace.dir)?,
+        })
+    }
+}
+
+/// The `MemAvailable` line of `/proc/meminfo`, in bytes.
+fn mem_available(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kibibytes: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    kibibytes.checked_mul(1024)
+}
+
+/// The bytes an unprivileged user can still write to the file system `dir`
+/// is on.
+fn free_space(dir: &Path) -> Result<u64> {
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is nul terminated and `stats` is only read once
+    // statvfs succeeded, which means it filled it in
+    let stats = unsafe {
+        if libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("couldn't statvfs {}", dir.display()));
+        }
+        stats.assume_init()
+    };
+    Ok(stats.f_bavail * stats.f_frsize)
+}
+
+/// The strategy to run and how, with why it was picked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub strategy: Strategy,
+    /// only used by [`Strategy::BufferedOnDisk`]
+    pub tiling: Tiling,
+    /// one sentence per step of the decision
+    pub reasons: Vec<String>,
+}
+
+/// Pick the fastest strategy that fits `resources` for a `dims` matrix.
+///
+/// That's the in-memory transpose if the input and its transpose both fit
+/// in memory. Otherwise it's the buffered one, with the tallest bands that
+/// fit over all columns, or with several passes over the input if even
+/// bands a page tall don't. With a `device` profile from `probe`, bands are
+/// at least tall enough for random writes to keep up with sequential ones.
+pub fn plan(
+    dims: Dimensions,
+    resources: Resources,
+    checkpoints: Checkpoints,
+    device: Option<&DeviceProfile>,
+) -> Result<Plan> {
+    let Dimensions {
+        size,
+        rows,
+        cols,
+        elem_size,
+        ..
+    } = dims;
+    let budget = (resources.available_memory as f64 * MEMORY_SHARE) as u64;
+    let mut reasons = vec![format!(
+        "{} of memory is available, so plan on using up to {}",
+        Size::from_bytes(resources.available_memory),
+        Size::from_bytes(budget),
+    )];
+    ensure!(
+        size <= resources.free_disk,
+        "the {} output doesn't fit in the {} free on disk",
+        Size::from_bytes(size),
+        Size::from_bytes(resources.free_disk),
+    );
+    reasons.push(format!(
+        "the {} output fits in the {} free on disk",
+        Size::from_bytes(size),
+        Size::from_bytes(resources.free_disk),
+    ));
+
+    let in_memory = size.saturating_mul(2);
+    if checkpoints != Checkpoints::Off {
+        reasons.push("only the buffered transpose can checkpoint".to_string());
+    } else if in_memory <= budget {
+        reasons.push(format!(
+            "the input and its transpose take {} in memory, which fits",
+            Size::from_bytes(in_memory),
+        ));
+        return Ok(Plan {
+            strategy: Strategy::InMemory,
+            tiling: Tiling::default(),
+            reasons,
+        });
+    } else {
+        reasons.push(format!(
+            "the input and its transpose would take {} in memory, which doesn't fit",
+            Size::from_bytes(in_memory),
+        ));
+    }
+
+    let min_write = match device.map(|device| device.efficient_write_size(MAX_WRITE as u64)) {
+        Some(size) => {
+            let size = size.map_or(MAX_WRITE, |size| (size as usize).max(MIN_WRITE));
+            reasons.push(format!(
+                "random writes of {} are about as fast as sequential ones on this device",
+                Size::from_bytes(size),
+            ));
+            size
+        }
+        None => MIN_WRITE,
+    };
+    // a band has at least one row, even of a matrix without any
+    let min_band = min_write.div_ceil(elem_size).min(rows).max(1);
+    let max_band = (MAX_WRITE / elem_size).clamp(min_band, rows.max(min_band));
+    let single_pass = |band_rows| Tiling {
+        band_rows,
+        pass_cols: None,
+    };
+    if single_pass(min_band).memory(dims) <= budget {
+        let mut band_rows = min_band;
+        while band_rows * 2 <= max_band && single_pass(band_rows * 2).memory(dims) <= budget {
+            band_rows *= 2;
+        }
+        let tiling = single_pass(band_rows);
+        reasons.push(format!(
+            "bands of {band_rows} rows over all {cols} columns take {}, so every write is {}",
+            Size::from_bytes(tiling.memory(dims)),
+            Size::from_bytes(band_rows * elem_size),
+        ));
+        return Ok(Plan {
+            strategy: Strategy::BufferedOnDisk,
+            tiling,
+            reasons,
+        });
+    }
+    let smallest = Size::from_bytes(single_pass(min_band).memory(dims));
+    ensure!(
+        checkpoints == Checkpoints::Off,
+        "bands of {min_band} rows over all {cols} columns take {smallest}, which doesn't fit, \
+         and a transpose in several passes can't checkpoint"
+    );
+
+    // a column costs its share of the band, and its element of the row
+    let per_col = ((min_band + 1) * elem_size) as u64;
+    let fixed = Tiling {
+        band_rows: min_band,
+        pass_cols: Some(0),
+    }
+    .memory(dims);
+    let pass_cols = (budget.saturating_sub(fixed) / per_col).min(cols as u64) as usize;
+    ensure!(
+        pass_cols > 0,
+        "there isn't enough memory for even one column of a band of {min_band} rows"
+    );
+    let tiling = Tiling {
+        band_rows: min_band,
+        pass_cols: Some(pass_cols),
+    };
+    let passes = tiling.passes(cols);
+    reasons.push(format!(
+        "bands of {min_band} rows over all {cols} columns would take {smallest}, which doesn't \
+         fit, so go over the input {passes} times, {pass_cols} columns at a time"
+    ));
+    Ok(Plan {
+        strategy: Strategy::BufferedOnDisk,
+        tiling,
+        reasons,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost::IopsAt;
+
+    const GIB: u64 = 2u64.pow(30);
+
+    fn resources(available_memory: u64) -> Resources {
+        Resources {
+            available_memory,
+            free_disk: 100 * GIB,
+        }
+    }
+
+    #[test]
+    fn reads_available_memory() {
+        let meminfo =
+            "MemTotal:       16310856 kB\nMemFree:         1234 kB\nMemAvailable:    8000000 kB\n";
+        assert_eq!(mem_available(meminfo), Some(8_000_000 * 1024));
+        assert_eq!(mem_available("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn picks_the_fastest_strategy_that_fits() {
+        let dims = Dimensions::from_log2_size(30);
+        let roomy = plan(dims, resources(4 * GIB), Checkpoints::Off, None).unwrap();
+        assert_eq!(roomy.strategy, Strategy::InMemory);
+
+        let tight = plan(dims, resources(GIB), Checkpoints::Off, None).unwrap();
+        assert_eq!(tight.strategy, Strategy::BufferedOnDisk);
+        assert_eq!(tight.tiling.pass_cols, None);
+        assert!(tight.tiling.band_rows * dims.elem_size >= MIN_WRITE);
+        assert!(tight.tiling.memory(dims) <= GIB);
+
+        let cramped = plan(dims, resources(64 * 2u64.pow(20)), Checkpoints::Off, None).unwrap();
+        assert_eq!(cramped.strategy, Strategy::BufferedOnDisk);
+        assert!(cramped.tiling.passes(dims.cols) > 1);
+        assert!(cramped.tiling.memory(dims) <= 64 * 2u64.pow(20));
+
+        // random writes never catch up, so bands are as tall as they get
+        let slow_random_writes = DeviceProfile {
+            random_write: vec![IopsAt {
+                block_size: 4096,
+                iops: 1e3,
+            }],
+            ..DeviceProfile::default()
+        };
+        let device = plan(
+            dims,
+            resources(GIB),
+            Checkpoints::Off,
+            Some(&slow_random_writes),
+        );
+        assert_eq!(device.unwrap().tiling.band_rows, dims.rows);
+    }
+
+    #[test]
+    fn checkpoints_and_disk_space_are_constraints() {
+        let dims = Dimensions::from_log2_size(30);
+        let checkpointed = plan(dims, resources(4 * GIB), Checkpoints::Record, None).unwrap();
+        assert_eq!(checkpointed.strategy, Strategy::BufferedOnDisk);
+        assert!(plan(dims, resources(2u64.pow(20)), Checkpoints::Record, None).is_err());
+
+        let full_disk = Resources {
+            available_memory: 4 * GIB,
+            free_disk: GIB - 1,
+        };
+        assert!(plan(dims, full_disk, Checkpoints::Off, None).is_err());
+
+        let empty = Dimensions::new(0, 4);
+        let checkpointed = plan(empty, resources(4 * GIB), Checkpoints::Record, None).unwrap();
+        assert_eq!(checkpointed.strategy, Strategy::BufferedOnDisk);
+        assert_eq!(checkpointed.tiling.band_rows, 1);
+    }
+}
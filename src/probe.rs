@@ -0,0 +1,284 @@
+use crate::cleanup::WorkFiles;
+use crate::cost::{DeviceProfile, IopsAt};
+use crate::generate::hash;
+use anyhow::{Context, Result, ensure};
+use size::Size;
+use std::fs::{File, OpenOptions};
+use std::hint::black_box;
+use std::io::{Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The block sizes random reads and writes are measured at.
+pub const BLOCK_SIZES: [u64; 4] = [512, 4096, 65536, 1 << 20];
+/// How long each random I/O and fsync measurement runs for.
+const MEASURE_TIME: Duration = Duration::from_secs(1);
+/// The block size of the sequential measurements.
+const SEQ_BLOCK: usize = 1 << 20;
+/// The side of the square matrix the shuffle rate is measured on.
+const SHUFFLE_SIDE: usize = 1 << 12;
+
+/// Measure what the storage `dir` is on can do, with a scratch file of
+/// `file_size` bytes that's deleted afterwards. Every measurement is
+/// reported to `report` as it's made.
+///
+/// Reads start from a cold cache as far as `posix_fadvise` can make them,
+/// which works without root but only for pages that are already written
+/// back, and random reads go without readahead and never hit a block twice
+/// between cache drops. Writes go through the page cache, as the strategies'
+/// do, and are timed up to and including the `fsync` that makes them durable.
+pub fn probe(dir: &Path, file_size: u64, report: impl Fn(&str)) -> Result<DeviceProfile> {
+    probe_for(dir, file_size, MEASURE_TIME, report)
+}
+
+/// [`probe`], with every random I/O and fsync measurement running for
+/// `measure_time`.
+fn probe_for(
+    dir: &Path,
+    file_size: u64,
+    measure_time: Duration,
+    report: impl Fn(&str),
+) -> Result<DeviceProfile> {
+    ensure!(
+        file_size >= BLOCK_SIZES[BLOCK_SIZES.len() - 1],
+        "the probe file has to hold at least one block of every size"
+    );
+    let mut work_files = WorkFiles::new(false);
+    let path = work_files.track(dir.join(format!(".probe-{}", std::process::id())));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .with_context(|| format!("couldn't create {}", path.display()))?;
+
+    let seq_write = seq_write(&mut file, file_size)?;
+    file.rewind()?;
+    report(&format!(
+        "sequential write {}/s",
+        Size::from_bytes(seq_write)
+    ));
+    drop_cache(&file)?;
+    let seq_read = seq_read(&mut file, file_size)?;
+    report(&format!(
+        "sequential read  {}/s",
+        Size::from_bytes(seq_read)
+    ));
+
+    let mut random_read = Vec::new();
+    let mut random_write = Vec::new();
+    for block_size in BLOCK_SIZES {
+        let iops = random_ops(&file, file_size, block_size, false, measure_time)?;
+        report(&format!(
+            "random read  {:>8} {iops:>10.0} IOPS",
+            Size::from_bytes(block_size)
+        ));
+        random_read.push(IopsAt { block_size, iops });
+        let iops = random_ops(&file, file_size, block_size, true, measure_time)?;
+        report(&format!(
+            "random write {:>8} {iops:>10.0} IOPS",
+            Size::from_bytes(block_size)
+        ));
+        random_write.push(IopsAt { block_size, iops });
+    }
+
+    let fsync_latency = fsync_latency(&file, file_size, measure_time)?;
+    report(&format!(
+        "fsync latency {:?}",
+        Duration::from_secs_f64(fsync_latency)
+    ));
+    let shuffle_rate = shuffle_rate();
+    report(&format!(
+        "in-memory transpose {shuffle_rate:.3e} elements/s"
+    ));
+
+    drop(file);
+    work_files.remove_all()?;
+    Ok(DeviceProfile {
+        seq_read: seq_read as f64,
+        seq_write: seq_write as f64,
+        random_read,
+        random_write,
+        fsync_latency,
+        shuffle_rate,
+    })
+}
+
+/// Bytes per second of writing `file_size` bytes front to back and syncing
+/// them.
+fn seq_write(file: &mut File, file_size: u64) -> Result<u64> {
+    // incompressible, in case the file system or device compresses
+    let block: Vec<u8> = (0..SEQ_BLOCK as u64)
+        .map(|index| hash(0, index) as u8)
+        .collect();
+    let start = Instant::now();
+    let mut written = 0;
+    while written < file_size {
+        let len = (file_size - written).min(SEQ_BLOCK as u64) as usize;
+        file.write_all(&block[..len])?;
+        written += len as u64;
+    }
+    file.sync_all()?;
+    Ok(rate(file_size, start.elapsed()))
+}
+
+/// Bytes per second of reading the whole file front to back.
+fn seq_read(file: &mut File, file_size: u64) -> Result<u64> {
+    let mut block = vec![0; SEQ_BLOCK];
+    let start = Instant::now();
+    let mut read = 0;
+    while read < file_size {
+        let len = (file_size - read).min(SEQ_BLOCK as u64) as usize;
+        file.read_exact(&mut block[..len])?;
+        read += len as u64;
+    }
+    Ok(rate(file_size, start.elapsed()))
+}
+
+/// Operations per second of reading (or writing) `block_size` blocks at
+/// scattered aligned offsets, for `measure_time`. Writes are synced at the
+/// end, and that counts too.
+///
+/// The blocks are visited in batches that hit every block once, and reads
+/// drop the cache before every batch, untimed, so none of them is served
+/// from memory.
+fn random_ops(
+    file: &File,
+    file_size: u64,
+    block_size: u64,
+    write: bool,
+    measure_time: Duration,
+) -> Result<f64> {
+    let blocks = file_size / block_size;
+    let mut block = vec![0x5a; block_size as usize];
+    if !write {
+        advise(file, libc::POSIX_FADV_RANDOM)?;
+    }
+    let mut elapsed = Duration::ZERO;
+    let mut ops = 0;
+    while elapsed < measure_time {
+        if !write {
+            drop_cache(file)?;
+        }
+        let start = Instant::now();
+        let batch_end = ops + blocks;
+        while ops < batch_end && elapsed + start.elapsed() < measure_time {
+            let offset = scattered(block_size, ops, blocks) * block_size;
+            if write {
+                file.write_all_at(&block, offset)?;
+            } else {
+                file.read_exact_at(&mut block, offset)?;
+            }
+            ops += 1;
+        }
+        elapsed += start.elapsed();
+    }
+    if write {
+        let start = Instant::now();
+        file.sync_data()?;
+        elapsed += start.elapsed();
+    } else {
+        advise(file, libc::POSIX_FADV_NORMAL)?;
+    }
+    Ok(ops as f64 / elapsed.as_secs_f64())
+}
+
+/// Which of `blocks` blocks the `op`th operation goes to, in an order that
+/// jumps around but hits every block once in each run of `blocks` operations.
+fn scattered(seed: u64, op: u64, blocks: u64) -> u64 {
+    // a prime bigger than any block count has no factor in common with it
+    const STEP: u128 = (1 << 61) - 1;
+    ((hash(seed, 0) as u128 + op as u128 * STEP) % blocks as u128) as u64
+}
+
+/// Seconds per `fsync` of a file with one freshly written page.
+fn fsync_latency(file: &File, file_size: u64, measure_time: Duration) -> Result<f64> {
+    let page = [0xa5; 4096];
+    let pages = file_size / page.len() as u64;
+    let start = Instant::now();
+    let mut syncs = 0;
+    while start.elapsed() < measure_time {
+        file.write_all_at(&page, hash(1, syncs) % pages * page.len() as u64)?;
+        file.sync_data()?;
+        syncs += 1;
+    }
+    Ok(start.elapsed().as_secs_f64() / syncs as f64)
+}
+
+/// Elements per second of transposing a byte matrix in memory, the same way
+/// the in-memory strategy does.
+fn shuffle_rate() -> f64 {
+    let input: Vec<u8> = (0..SHUFFLE_SIDE * SHUFFLE_SIDE)
+        .map(|index| index as u8)
+        .collect();
+    let mut output = vec![0; input.len()];
+    let start = Instant::now();
+    for i in 0..SHUFFLE_SIDE {
+        for j in 0..SHUFFLE_SIDE {
+            output[j * SHUFFLE_SIDE + i] = input[i * SHUFFLE_SIDE + j];
+        }
+    }
+    black_box(&output);
+    input.len() as f64 / start.elapsed().as_secs_f64()
+}
+
+/// Ask the kernel to drop the cached pages of `file`, so it's read from the
+/// device again.
+fn drop_cache(file: &File) -> Result<()> {
+    file.sync_all()?;
+    advise(file, libc::POSIX_FADV_DONTNEED)
+}
+
+/// Tell the kernel how the whole of `file` is going to be read.
+fn advise(file: &File, advice: libc::c_int) -> Result<()> {
+    // SAFETY: plain syscall on a file descriptor that's open for as long as
+    // `file` is borrowed
+    let error = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice) };
+    ensure!(
+        error == 0,
+        "couldn't advise the kernel on the probe file: {}",
+        std::io::Error::from_raw_os_error(error)
+    );
+    Ok(())
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> u64 {
+    (bytes as f64 / elapsed.as_secs_f64()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_every_block_size() {
+        let dir = std::env::temp_dir();
+        let profile = probe_for(&dir, 2 << 20, Duration::from_millis(10), |_| {}).unwrap();
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        for (name, value) in [
+            ("seq_read", profile.seq_read),
+            ("seq_write", profile.seq_write),
+            ("fsync_latency", profile.fsync_latency),
+            ("shuffle_rate", profile.shuffle_rate),
+        ] {
+            assert!(positive(value), "{name} is {value}");
+        }
+        for measured in [&profile.random_read, &profile.random_write] {
+            let block_sizes: Vec<u64> = measured.iter().map(|at| at.block_size).collect();
+            assert_eq!(block_sizes, BLOCK_SIZES);
+            assert!(measured.iter().all(|at| positive(at.iops)), "{measured:?}");
+        }
+    }
+
+    #[test]
+    fn scatters_over_every_block() {
+        for blocks in [1, 2, 6, 1000] {
+            let mut hit: Vec<u64> = (0..blocks).map(|op| scattered(7, op, blocks)).collect();
+            hit.sort();
+            assert_eq!(hit, (0..blocks).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::json;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Where strategies (and input generation) report how far they've got.
+///
+/// Work comes in stages, e.g. the join solution first splits the input into
+/// column files and then concatenates them. Each stage is announced with
+/// [`start`](Self::start), reported on with [`advance`](Self::advance) as
+/// rows get done, and ended with [`finish`](Self::finish).
+pub trait Progress: Sync {
+    /// A new stage covering `rows` rows and `bytes` bytes.
+    fn start(&self, stage: &str, rows: u64, bytes: u64);
+    /// Another `rows` rows and `bytes` bytes of the current stage are done.
+    fn advance(&self, rows: u64, bytes: u64);
+    /// The current stage is done.
+    fn finish(&self);
+}
+
+/// The [`Progress`] implementations the CLI can pick from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressKind {
+    /// a progress bar on the terminal
+    #[default]
+    Bar,
+    /// nothing at all
+    Silent,
+    /// one JSON object per line on stderr, for other tools to follow
+    Ndjson,
+}
+
+impl ProgressKind {
+    pub fn sink(self) -> Box<dyn Progress> {
+        match self {
+            ProgressKind::Bar => Box::new(Bar::default()),
+            ProgressKind::Silent => Box::new(Silent),
+            ProgressKind::Ndjson => Box::new(Ndjson::new(std::io::stderr())),
+        }
+    }
+}
+
+/// Reports nothing.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Silent;
+
+impl Progress for Silent {
+    fn start(&self, _stage: &str, _rows: u64, _bytes: u64) {}
+    fn advance(&self, _rows: u64, _bytes: u64) {}
+    fn finish(&self) {}
+}
+
+/// An indicatif progress bar per stage, cleared once the stage is done.
+#[derive(Debug, Default)]
+pub struct Bar {
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl Progress for Bar {
+    fn start(&self, stage: &str, _rows: u64, bytes: u64) {
+        let bar = ProgressBar::new(bytes).with_message(stage.to_string());
+        bar.set_style(
+            ProgressStyle::with_template("{msg} {wide_bar} {bytes}/{total_bytes} ({eta})")
+                .expect("the template is valid"),
+        );
+        if let Some(old) = self.bar.lock().unwrap().replace(bar) {
+            old.finish_and_clear();
+        }
+    }
+
+    fn advance(&self, _rows: u64, bytes: u64) {
+        if let Some(bar) = &*self.bar.lock().unwrap() {
+            bar.inc(bytes);
+        }
+    }
+
+    fn finish(&self) {
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// How often [`Ndjson`] reports progress within a stage.
+const NDJSON_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Newline-delimited JSON events: a `start` and a `finish` per stage, and
+/// `progress` at most every 100ms in between, each with the rows and bytes
+/// done and in total so far. Failing to write them doesn't stop the run.
+#[derive(Debug)]
+pub struct Ndjson<W> {
+    state: Mutex<NdjsonState<W>>,
+}
+
+#[derive(Debug)]
+struct NdjsonState<W> {
+    writer: W,
+    stage: String,
+    total: (u64, u64),
+    done: (u64, u64),
+    last_report: Instant,
+}
+
+impl<W: Write> Ndjson<W> {
+    pub fn new(writer: W) -> Self {
+        Ndjson {
+            state: Mutex::new(NdjsonState {
+                writer,
+                stage: String::new(),
+                total: (0, 0),
+                done: (0, 0),
+                last_report: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.state.into_inner().unwrap().writer
+    }
+}
+
+impl<W: Write> NdjsonState<W> {
+    fn report(&mut self, event: &str) {
+        let line = json!({
+            "event": event,
+            "stage": self.stage,
+            "rows": self.done.0,
+            "total_rows": self.total.0,
+            "bytes": self.done.1,
+            "total_bytes": self.total.1,
+        });
+        let _ = writeln!(self.writer, "{line}").and_then(|()| self.writer.flush());
+        self.last_report = Instant::now();
+    }
+}
+
+impl<W: Write + Send> Progress for Ndjson<W> {
+    fn start(&self, stage: &str, rows: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.stage = stage.to_string();
+        state.total = (rows, bytes);
+        state.done = (0, 0);
+        state.report("start");
+    }
+
+    fn advance(&self, rows: u64, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.done.0 += rows;
+        state.done.1 += bytes;
+        if state.last_report.elapsed() >= NDJSON_INTERVAL {
+            state.report("progress");
+        }
+    }
+
+    fn finish(&self) {
+        self.state.lock().unwrap().report("finish");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ndjson_reports_every_stage() {
+        let progress = Ndjson::new(Vec::new());
+        progress.start("split", 4, 40);
+        for _ in 0..4 {
+            progress.advance(1, 10);
+        }
+        progress.finish();
+        progress.start("concatenate", 2, 40);
+        progress.advance(2, 40);
+        progress.finish();
+
+        let output = String::from_utf8(progress.into_inner()).unwrap();
+        let events: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // progress events depend on timing, the others don't
+        let summary: Vec<(&str, &str, u64)> = events
+            .iter()
+            .filter(|event| event["event"] != "progress")
+            .map(|event| {
+                (
+                    event["event"].as_str().unwrap(),
+                    event["stage"].as_str().unwrap(),
+                    event["bytes"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("start", "split", 0),
+                ("finish", "split", 40),
+                ("start", "concatenate", 0),
+                ("finish", "concatenate", 40),
+            ]
+        );
+    }
+}
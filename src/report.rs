@@ -0,0 +1,406 @@
+use crate::Strategy;
+use crate::cost::Direction;
+use crate::results::RunResults;
+use crate::timing::Phase;
+use anyhow::Result;
+use size::Size;
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+const CHART_WIDTH: f64 = 760.0;
+const CHART_HEIGHT: f64 = 400.0;
+const MARGIN_LEFT: f64 = 90.0;
+const MARGIN_RIGHT: f64 = 170.0;
+const MARGIN_TOP: f64 = 20.0;
+const MARGIN_BOTTOM: f64 = 50.0;
+
+const STRATEGY_COLORS: [&str; Strategy::ALL.len()] =
+    ["#8e44ad", "#d4ac0d", "#2e86c1", "#28b463", "#17a589"];
+const PHASE_COLORS: [&str; Phase::ALL.len()] = [
+    "#bdc3c7", "#3498db", "#e67e22", "#e74c3c", "#9b59b6", "#7f8c8d",
+];
+const UNKNOWN_COLOR: &str = "#555555";
+
+/// Write a standalone HTML report of `results` to `path`.
+pub fn write_report(path: &Path, results: &[RunResults]) -> Result<()> {
+    std::fs::write(path, render_report(results))?;
+    Ok(())
+}
+
+/// A standalone HTML page with inline SVG charts of `results`: throughput
+/// against file size per strategy, the phase breakdown and the I/O syscall
+/// counts of every run. It doesn't load any external assets.
+pub fn render_report(results: &[RunResults]) -> String {
+    let mut results: Vec<&RunResults> = results.iter().collect();
+    results.sort_by_key(|results| results.size);
+
+    let mut html = String::new();
+    write_page(&mut html, &results).expect("writing to a String can't fail");
+    html
+}
+
+fn write_page(html: &mut String, results: &[&RunResults]) -> fmt::Result {
+    writeln!(html, "<!DOCTYPE html>")?;
+    writeln!(
+        html,
+        "<html><head><meta charset=\"utf-8\"><title>transpose report</title>"
+    )?;
+    writeln!(
+        html,
+        "<style>body{{font-family:sans-serif;margin:2em}}table{{border-collapse:collapse;margin-bottom:2em}}\
+         td,th{{border:1px solid #ccc;padding:4px 8px;text-align:right}}th{{background:#eee}}\
+         td:first-child{{text-align:left}}svg{{display:block;margin-bottom:2em}}</style>"
+    )?;
+    writeln!(html, "</head><body>")?;
+    writeln!(html, "<h1>Transpose report</h1>")?;
+
+    writeln!(html, "<h2>Throughput against file size</h2>")?;
+    write_throughput_chart(html, results)?;
+
+    writeln!(html, "<h2>Phase breakdown</h2>")?;
+    for results in results {
+        writeln!(
+            html,
+            "<h3>{} ({} cols by {} rows)</h3>",
+            Size::from_bytes(results.size),
+            results.cols,
+            results.rows
+        )?;
+        write_phase_chart(html, results)?;
+    }
+
+    writeln!(html, "<h2>I/O syscalls per run</h2>")?;
+    write_syscall_table(html, results)?;
+
+    if results.iter().any(|results| results.device.is_some()) {
+        writeln!(html, "<h2>Devices</h2>")?;
+        write_device_table(html, results)?;
+    }
+
+    writeln!(html, "</body></html>")
+}
+
+fn strategy_color(name: &str) -> &'static str {
+    Strategy::ALL
+        .iter()
+        .position(|strategy| strategy.name() == name)
+        .map_or(UNKNOWN_COLOR, |index| STRATEGY_COLORS[index])
+}
+
+/// Every strategy name that shows up in `results`, in [`Strategy::ALL`] order
+/// with unknown names at the end.
+fn strategy_names(results: &[&RunResults]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for strategy_result in results.iter().flat_map(|results| &results.strategies) {
+        if !names.contains(&strategy_result.strategy) {
+            names.push(strategy_result.strategy.clone());
+        }
+    }
+    names.sort_by_key(|name| {
+        Strategy::ALL
+            .iter()
+            .position(|strategy| strategy.name() == name)
+            .unwrap_or(usize::MAX)
+    });
+    names
+}
+
+fn write_throughput_chart(html: &mut String, results: &[&RunResults]) -> fmt::Result {
+    let names = strategy_names(results);
+    // (log2 size, log10 bytes per second) for every strategy
+    let series: Vec<(&String, Vec<(f64, f64)>)> = names
+        .iter()
+        .map(|name| {
+            let points = results
+                .iter()
+                .filter_map(|results| {
+                    let mean = results.strategy(name)?.headline_summary().mean;
+                    (mean > 0.0).then(|| {
+                        (
+                            (results.size as f64).log2(),
+                            (results.size as f64 / mean).log10(),
+                        )
+                    })
+                })
+                .collect();
+            (name, points)
+        })
+        .collect();
+    let all_points = || series.iter().flat_map(|(_, points)| points.iter());
+    if all_points().next().is_none() {
+        return writeln!(html, "<p>no timings to plot</p>");
+    }
+
+    let (mut x_min, mut x_max) = min_max(all_points().map(|(x, _)| *x));
+    if x_min == x_max {
+        x_min -= 1.0;
+        x_max += 1.0;
+    }
+    let (y_min, y_max) = min_max(all_points().map(|(_, y)| *y));
+    let (y_min, y_max) = (y_min.floor(), y_max.ceil().max(y_min.floor() + 1.0));
+    let plot_width = CHART_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = CHART_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+    let x_pos = |x: f64| MARGIN_LEFT + (x - x_min) / (x_max - x_min) * plot_width;
+    let y_pos = |y: f64| MARGIN_TOP + (y_max - y) / (y_max - y_min) * plot_height;
+
+    writeln!(
+        html,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{CHART_WIDTH}\" height=\"{CHART_HEIGHT}\" font-size=\"12\">"
+    )?;
+    // one horizontal grid line per decade of throughput
+    for decade in (y_min as i32)..=(y_max as i32) {
+        let y = y_pos(decade as f64);
+        let label = Size::from_bytes(10f64.powi(decade));
+        writeln!(
+            html,
+            "<line x1=\"{MARGIN_LEFT}\" x2=\"{:.1}\" y1=\"{y:.1}\" y2=\"{y:.1}\" stroke=\"#ddd\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{label}/s</text>",
+            MARGIN_LEFT + plot_width,
+            MARGIN_LEFT - 6.0,
+            y + 4.0,
+        )?;
+    }
+    for results in results {
+        let x = x_pos((results.size as f64).log2());
+        writeln!(
+            html,
+            "<line x1=\"{x:.1}\" x2=\"{x:.1}\" y1=\"{MARGIN_TOP}\" y2=\"{:.1}\" stroke=\"#eee\"/>\
+             <text x=\"{x:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            MARGIN_TOP + plot_height,
+            MARGIN_TOP + plot_height + 18.0,
+            Size::from_bytes(results.size),
+        )?;
+    }
+    writeln!(
+        html,
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">file size</text>",
+        MARGIN_LEFT + plot_width / 2.0,
+        CHART_HEIGHT - 8.0,
+    )?;
+
+    for (index, (name, points)) in series.iter().enumerate() {
+        let color = strategy_color(name);
+        let path: Vec<String> = points
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", x_pos(*x), y_pos(*y)))
+            .collect();
+        writeln!(
+            html,
+            "<polyline fill=\"none\" stroke=\"{color}\" stroke-width=\"2\" points=\"{}\"/>",
+            path.join(" ")
+        )?;
+        for (x, y) in points {
+            writeln!(
+                html,
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3.5\" fill=\"{color}\"><title>{} {}/s</title></circle>",
+                x_pos(*x),
+                y_pos(*y),
+                escape(name),
+                Size::from_bytes(10f64.powf(*y)),
+            )?;
+        }
+        let legend_y = MARGIN_TOP + 10.0 + index as f64 * 18.0;
+        writeln!(
+            html,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"12\" height=\"12\" fill=\"{color}\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+            CHART_WIDTH - MARGIN_RIGHT + 16.0,
+            legend_y - 10.0,
+            CHART_WIDTH - MARGIN_RIGHT + 34.0,
+            legend_y,
+            escape(name),
+        )?;
+    }
+    writeln!(html, "</svg>")
+}
+
+/// One stacked horizontal bar per strategy, with a segment per phase.
+fn write_phase_chart(html: &mut String, results: &RunResults) -> fmt::Result {
+    const BAR_HEIGHT: f64 = 22.0;
+    const BAR_GAP: f64 = 8.0;
+    let bars: Vec<_> = results
+        .strategies
+        .iter()
+        .map(|result| (&result.strategy, result.mean_timings()))
+        .collect();
+    let longest = bars
+        .iter()
+        .map(|(_, timings)| timings.total().as_secs_f64())
+        .fold(0.0, f64::max);
+    if longest == 0.0 {
+        return writeln!(html, "<p>no timings to plot</p>");
+    }
+
+    let plot_width = CHART_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let height = MARGIN_TOP + bars.len().max(Phase::ALL.len()) as f64 * (BAR_HEIGHT + BAR_GAP);
+    writeln!(
+        html,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{CHART_WIDTH}\" height=\"{height:.1}\" font-size=\"12\">"
+    )?;
+    for (index, (name, timings)) in bars.iter().enumerate() {
+        let y = MARGIN_TOP + index as f64 * (BAR_HEIGHT + BAR_GAP);
+        writeln!(
+            html,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            MARGIN_LEFT - 6.0,
+            y + BAR_HEIGHT / 2.0 + 4.0,
+            escape(name),
+        )?;
+        let mut x = MARGIN_LEFT;
+        for (phase, color) in Phase::ALL.into_iter().zip(PHASE_COLORS) {
+            let duration = timings.get(phase);
+            let width = duration.as_secs_f64() / longest * plot_width;
+            writeln!(
+                html,
+                "<rect x=\"{x:.1}\" y=\"{y:.1}\" width=\"{width:.1}\" height=\"{BAR_HEIGHT}\" fill=\"{color}\">\
+                 <title>{} {}: {duration:?}</title></rect>",
+                escape(name),
+                phase.name(),
+            )?;
+            x += width;
+        }
+        writeln!(
+            html,
+            "<text x=\"{:.1}\" y=\"{:.1}\">{:.3?}</text>",
+            x + 4.0,
+            y + BAR_HEIGHT / 2.0 + 4.0,
+            timings.headline(),
+        )?;
+    }
+    for (index, (phase, color)) in Phase::ALL.into_iter().zip(PHASE_COLORS).enumerate() {
+        let legend_y = MARGIN_TOP + 10.0 + index as f64 * 18.0;
+        let counted = if phase.counts_toward_headline() {
+            ""
+        } else {
+            " (not counted)"
+        };
+        writeln!(
+            html,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"12\" height=\"12\" fill=\"{color}\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\">{}{counted}</text>",
+            CHART_WIDTH - MARGIN_RIGHT + 60.0,
+            legend_y - 10.0,
+            CHART_WIDTH - MARGIN_RIGHT + 78.0,
+            legend_y,
+            phase.name(),
+        )?;
+    }
+    writeln!(html, "</svg>")
+}
+
+fn write_syscall_table(html: &mut String, results: &[&RunResults]) -> fmt::Result {
+    writeln!(
+        html,
+        "<table><tr><th>strategy</th><th>file size</th><th>read syscalls</th>\
+         <th>write syscalls</th><th>bytes read</th><th>bytes written</th></tr>"
+    )?;
+    for results in results {
+        for result in &results.strategies {
+            let Some(io) = result.mean_io() else {
+                continue;
+            };
+            writeln!(
+                html,
+                "<tr><td><span style=\"color:{}\">&#9632;</span> {}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                strategy_color(&result.strategy),
+                escape(&result.strategy),
+                Size::from_bytes(results.size),
+                io.read_syscalls,
+                io.write_syscalls,
+                Size::from_bytes(io.read_chars),
+                Size::from_bytes(io.written_chars),
+            )?;
+        }
+    }
+    writeln!(html, "</table>")
+}
+
+/// What `probe` measured of the device of every run that recorded it.
+fn write_device_table(html: &mut String, results: &[&RunResults]) -> fmt::Result {
+    writeln!(
+        html,
+        "<table><tr><th>file size</th><th>sequential read</th><th>sequential write</th>\
+         <th>4 KiB random read</th><th>4 KiB random write</th><th>fsync</th>\
+         <th>in-memory transpose</th></tr>"
+    )?;
+    for results in results {
+        let Some(device) = &results.device else {
+            continue;
+        };
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}/s</td><td>{}/s</td><td>{:.0} IOPS</td><td>{:.0} IOPS</td>\
+             <td>{:.2?}</td><td>{:.3e} elements/s</td></tr>",
+            Size::from_bytes(results.size),
+            Size::from_bytes(device.seq_read),
+            Size::from_bytes(device.seq_write),
+            device.random_iops(Direction::Read, 4096),
+            device.random_iops(Direction::Write, 4096),
+            Duration::from_secs_f64(device.fsync_latency),
+            device.shuffle_rate,
+        )?;
+    }
+    writeln!(html, "</table>")
+}
+
+fn min_max(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+        (min.min(value), max.max(value))
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dimensions;
+    use crate::cost::DeviceProfile;
+    use crate::io_counters::IoCounters;
+    use crate::results::StrategyResult;
+    use crate::timing::PhaseTimings;
+    use std::time::Duration;
+
+    #[test]
+    fn report_is_self_contained() {
+        let mut results: Vec<RunResults> = [10, 14]
+            .into_iter()
+            .map(|log2_size| {
+                let mut results = RunResults::new(Dimensions::from_log2_size(log2_size), None);
+                for (index, strategy) in Strategy::ALL.into_iter().enumerate() {
+                    let mut timings = PhaseTimings::default();
+                    for phase in Phase::ALL {
+                        timings.record(
+                            phase,
+                            Duration::from_micros((index as u64 + 1) << log2_size),
+                        );
+                    }
+                    results.strategies.push(StrategyResult {
+                        strategy: strategy.name().to_string(),
+                        runs: vec![timings],
+                        io: vec![IoCounters::default()],
+                        cancelled: false,
+                    });
+                }
+                results
+            })
+            .collect();
+        let html = render_report(&results);
+        assert_eq!(html.matches("<svg").count(), 3);
+        assert_eq!(html.matches("<tr>").count(), 1 + 2 * Strategy::ALL.len());
+        for external in ["src=", "href=", "url(", "@import"] {
+            assert!(!html.contains(external), "report references {external}");
+        }
+
+        results[1].device = Some(DeviceProfile::default());
+        let html = render_report(&results);
+        assert!(html.contains("<h2>Devices</h2>"));
+        assert_eq!(html.matches("<tr>").count(), 3 + 2 * Strategy::ALL.len());
+    }
+}
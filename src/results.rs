@@ -0,0 +1,163 @@
+use crate::Dimensions;
+use crate::axes::Axes;
+use crate::cost::DeviceProfile;
+use crate::generate::Generator;
+use crate::io_counters::IoCounters;
+use crate::timing::PhaseTimings;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Everything a run measured, in a form that can be stored and compared
+/// against later runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunResults {
+    pub size: u64,
+    pub rows: usize,
+    pub cols: usize,
+    /// bytes per element
+    #[serde(default = "one")]
+    pub elem_size: usize,
+    /// the axes of the N-d array the run permuted, like
+    /// [`Dimensions::permuted`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permuted: Option<Axes>,
+    /// what generated the input, `None` when it was supplied by the user
+    #[serde(default)]
+    pub generator: Option<Generator>,
+    /// the absolute path of the --input file, `None` when it was generated
+    #[serde(default)]
+    pub input: Option<PathBuf>,
+    /// what `probe` measured of the device the run was on, if it was given
+    #[serde(default)]
+    pub device: Option<DeviceProfile>,
+    pub strategies: Vec<StrategyResult>,
+}
+
+/// The timings of every repetition of a single strategy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StrategyResult {
+    /// [`Strategy::name`](crate::Strategy::name) of the strategy that ran
+    pub strategy: String,
+    pub runs: Vec<PhaseTimings>,
+    /// the I/O counters of every run, empty where they aren't available
+    #[serde(default)]
+    pub io: Vec<IoCounters>,
+    /// whether the strategy was interrupted, so `runs` may be short
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
+impl RunResults {
+    pub fn new(
+        Dimensions {
+            size,
+            rows,
+            cols,
+            elem_size,
+            permuted,
+            ..
+        }: Dimensions,
+        generator: Option<Generator>,
+    ) -> Self {
+        RunResults {
+            size,
+            rows,
+            cols,
+            elem_size,
+            permuted,
+            generator,
+            input: None,
+            device: None,
+            strategies: Vec::new(),
+        }
+    }
+
+    pub fn strategy(&self, name: &str) -> Option<&StrategyResult> {
+        self.strategies
+            .iter()
+            .find(|result| result.strategy == name)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("no results at {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("couldn't parse results at {}", path.display()))
+    }
+}
+
+fn one() -> usize {
+    1
+}
+
+impl StrategyResult {
+    /// Summary statistics of the headline time, in seconds.
+    pub fn headline_summary(&self) -> Summary {
+        Summary::of(
+            self.runs
+                .iter()
+                .map(|timings| timings.headline().as_secs_f64()),
+        )
+    }
+
+    /// The per-phase average over all runs.
+    pub fn mean_timings(&self) -> PhaseTimings {
+        let mut total = PhaseTimings::default();
+        for timings in &self.runs {
+            total += *timings;
+        }
+        total / self.runs.len().max(1) as u32
+    }
+
+    /// The average I/O counters over all runs, if they were measured.
+    pub fn mean_io(&self) -> Option<IoCounters> {
+        let runs = self.io.len() as u64;
+        if runs == 0 {
+            return None;
+        }
+        let mean = |field: fn(&IoCounters) -> u64| self.io.iter().map(field).sum::<u64>() / runs;
+        Some(IoCounters {
+            read_syscalls: mean(|io| io.read_syscalls),
+            write_syscalls: mean(|io| io.write_syscalls),
+            read_chars: mean(|io| io.read_chars),
+            written_chars: mean(|io| io.written_chars),
+        })
+    }
+}
+
+/// Mean and sample standard deviation of a set of measurements.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+}
+
+impl Summary {
+    pub fn of(samples: impl IntoIterator<Item = f64>) -> Self {
+        let samples: Vec<f64> = samples.into_iter().collect();
+        let count = samples.len();
+        let mean = samples.iter().sum::<f64>() / count as f64;
+        let std_dev = if count > 1 {
+            let variance =
+                samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1) as f64;
+            variance.sqrt()
+        } else {
+            0.0
+        };
+        Summary {
+            count,
+            mean,
+            std_dev,
+        }
+    }
+}
@@ -0,0 +1,778 @@
+use crate::cancel::Cancellation;
+use crate::generate::hash;
+use crate::npy::NpyHeader;
+use crate::progress::Progress;
+use crate::strategies::Tiling;
+use crate::timing::{Phase, PhaseTimings};
+use crate::verify::fnv1a;
+use anyhow::{Context, Result, bail, ensure};
+use std::fmt;
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+const READ_BUFF_SIZE: usize = 2usize.pow(20);
+
+/// Which axis a sparse matrix is compressed along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// compressed rows: `indptr` has an entry per row, `indices` are columns
+    Csr,
+    /// compressed columns: `indptr` has an entry per column, `indices` are
+    /// rows
+    Csc,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Csr => "csr",
+            Format::Csc => "csc",
+        }
+    }
+
+    /// The format the same arrays are in for the transpose.
+    pub fn other(self) -> Self {
+        match self {
+            Format::Csr => Format::Csc,
+            Format::Csc => Format::Csr,
+        }
+    }
+}
+
+/// One of the `.npy` files of a compressed matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Array {
+    path: PathBuf,
+    header: NpyHeader,
+    /// where the payload starts
+    offset: u64,
+    /// elements, of every dimension
+    len: u64,
+    elem_size: usize,
+}
+
+impl Array {
+    /// The `name.npy` file in `dir`, which has to have `dims` dimensions.
+    fn open(dir: &Path, name: &str, dims: usize) -> Result<Self> {
+        let path = dir.join(format!("{name}.npy"));
+        let file =
+            File::open(&path).with_context(|| format!("couldn't open {}", path.display()))?;
+        let (header, offset) = NpyHeader::read(&mut BufReader::new(&file))
+            .with_context(|| format!("{} isn't a usable .npy file", path.display()))?;
+        ensure!(
+            header.shape.len() == dims,
+            "{} should have {dims} dimensions, not shape {:?}",
+            path.display(),
+            header.shape
+        );
+        let len = header.elements()?;
+        let elem_size = header.elem_size()?;
+        let expected = header.payload_len()?;
+        let payload_len = file.metadata()?.len() - offset;
+        ensure!(
+            payload_len == expected,
+            "{} should have {expected} bytes of data after its header, but has {payload_len}",
+            path.display(),
+        );
+        Ok(Array {
+            path,
+            header,
+            offset,
+            len,
+            elem_size,
+        })
+    }
+
+    /// Create `name.npy` in `dir` with room for `len` elements of `descr`,
+    /// to be filled in with [`write_at`](Self::write_at).
+    fn create(dir: &Path, name: &str, descr: &str, len: u64) -> Result<(Self, File)> {
+        let path = dir.join(format!("{name}.npy"));
+        let header = NpyHeader {
+            descr: descr.to_string(),
+            fortran_order: false,
+            shape: vec![usize::try_from(len)?],
+        };
+        let bytes = header.to_bytes();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("couldn't create {}", path.display()))?;
+        let elem_size = header.elem_size()?;
+        file.write_all_at(&bytes, 0)?;
+        file.set_len(bytes.len() as u64 + header.payload_len()?)?;
+        let array = Array {
+            path,
+            header,
+            offset: bytes.len() as u64,
+            len,
+            elem_size,
+        };
+        Ok((array, file))
+    }
+
+    /// Write `bytes` of whole elements to `file`, starting at element
+    /// `index`.
+    fn write_at(&self, file: &File, bytes: &[u8], index: u64) -> std::io::Result<()> {
+        file.write_all_at(bytes, self.offset + index * self.elem_size as u64)
+    }
+
+    /// A reader of the payload from its start.
+    fn reader(&self) -> Result<BufReader<File>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok(BufReader::with_capacity(READ_BUFF_SIZE, file))
+    }
+
+    /// The bytes of an index, which has to be a little endian integer.
+    fn index_width(&self) -> Result<usize> {
+        match self.header.descr.as_str() {
+            "<i4" | "<u4" => Ok(4),
+            "<i8" | "<u8" => Ok(8),
+            descr => bail!(
+                "{} holds {descr}, but indices have to be 4 or 8 byte little endian integers",
+                self.path.display()
+            ),
+        }
+    }
+
+    /// The biggest index this dtype holds.
+    fn max_index(&self) -> u64 {
+        match self.header.descr.as_str() {
+            "<i4" => i32::MAX as u64,
+            "<u4" => u32::MAX as u64,
+            "<i8" => i64::MAX as u64,
+            _ => u64::MAX,
+        }
+    }
+}
+
+/// Read a `width` byte little endian index. Negative ones come out too big
+/// for any matrix, so range checks catch them.
+fn read_index(reader: &mut impl Read, width: usize) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes[..width])?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// A sparse matrix in CSR or CSC format, stored in a directory like scipy's
+/// `save_npz` stores one, unzipped: `indptr.npy`, `indices.npy`,
+/// `data.npy`, `shape.npy` and `format.npy`. Without a `format.npy` it's
+/// CSR.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compressed {
+    pub dir: PathBuf,
+    pub format: Format,
+    /// rows and columns
+    pub shape: [u64; 2],
+    indptr: Array,
+    indices: Array,
+    data: Array,
+}
+
+impl Compressed {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let format = if dir.join("format.npy").exists() {
+            let array = Array::open(dir, "format", 0)?;
+            let mut bytes = Vec::new();
+            array.reader()?.read_to_end(&mut bytes)?;
+            let name: String = match array.header.descr.as_str() {
+                // unicode strings are UCS-4
+                "<U3" => bytes
+                    .chunks_exact(4)
+                    .filter_map(|char| char::from_u32(u32::from_le_bytes(char.try_into().ok()?)))
+                    .collect(),
+                "|S3" => String::from_utf8_lossy(&bytes).into_owned(),
+                descr => bail!("format.npy holds {descr}, not a 3 letter string"),
+            };
+            match name.as_str() {
+                "csr" => Format::Csr,
+                "csc" => Format::Csc,
+                _ => bail!("{} is a {name} matrix, not csr or csc", dir.display()),
+            }
+        } else {
+            Format::Csr
+        };
+
+        let shape_array = Array::open(dir, "shape", 1)?;
+        ensure!(shape_array.len == 2, "shape.npy should hold 2 lengths");
+        let width = shape_array.index_width()?;
+        let mut reader = shape_array.reader()?;
+        let shape = [
+            read_index(&mut reader, width)?,
+            read_index(&mut reader, width)?,
+        ];
+        // either axis can be the major one of an indptr, which has to fit in
+        // a file
+        ensure!(
+            shape.iter().all(|&len| len < u64::MAX / 8),
+            "shape {shape:?} is too big"
+        );
+
+        let matrix = Compressed {
+            dir: dir.to_path_buf(),
+            format,
+            shape,
+            indptr: Array::open(dir, "indptr", 1)?,
+            indices: Array::open(dir, "indices", 1)?,
+            data: Array::open(dir, "data", 1)?,
+        };
+        let (major, _) = matrix.axes();
+        ensure!(
+            matrix.indptr.len == major + 1,
+            "indptr.npy has {} entries, but a {} matrix of shape {shape:?} needs {}",
+            matrix.indptr.len,
+            format.name(),
+            major + 1
+        );
+        ensure!(
+            matrix.indices.len == matrix.data.len,
+            "indices.npy has {} entries, but data.npy has {}",
+            matrix.indices.len,
+            matrix.data.len
+        );
+        matrix.indptr.index_width()?;
+        matrix.indices.index_width()?;
+        Ok(matrix)
+    }
+
+    /// How many rows (CSR) or columns (CSC) are compressed, and how many of
+    /// the others there are.
+    pub fn axes(&self) -> (u64, u64) {
+        match self.format {
+            Format::Csr => (self.shape[0], self.shape[1]),
+            Format::Csc => (self.shape[1], self.shape[0]),
+        }
+    }
+
+    /// Stored entries, zeros and duplicates included.
+    pub fn nnz(&self) -> u64 {
+        self.indices.len
+    }
+
+    /// Bytes per value.
+    pub fn elem_size(&self) -> usize {
+        self.data.elem_size
+    }
+
+    fn rows(&self) -> Result<Rows> {
+        Ok(Rows {
+            indptr: self.indptr.reader()?,
+            indices: self.indices.reader()?,
+            data: self.data.reader()?,
+            indptr_width: self.indptr.index_width()?,
+            index_width: self.indices.index_width()?,
+            elem_size: self.data.elem_size,
+            axes: self.axes(),
+            nnz: self.nnz(),
+            row: 0,
+            end: None,
+        })
+    }
+}
+
+/// Reads a compressed matrix one row (CSR) or column (CSC) at a time,
+/// checking the arrays agree along the way.
+struct Rows {
+    indptr: BufReader<File>,
+    indices: BufReader<File>,
+    data: BufReader<File>,
+    indptr_width: usize,
+    index_width: usize,
+    elem_size: usize,
+    axes: (u64, u64),
+    nnz: u64,
+    /// the next one to read
+    row: u64,
+    /// where the last one read ended
+    end: Option<u64>,
+}
+
+impl Rows {
+    /// Read the next row's indices and values into `indices` and `values`,
+    /// or return `false` after the last one.
+    fn next(&mut self, indices: &mut Vec<u64>, values: &mut Vec<u8>) -> Result<bool> {
+        let (major, minor) = self.axes;
+        let start = match self.end {
+            Some(end) => end,
+            None => {
+                let start = read_index(&mut self.indptr, self.indptr_width)?;
+                ensure!(start == 0, "indptr.npy starts at {start}, not 0");
+                start
+            }
+        };
+        if self.row == major {
+            ensure!(
+                start == self.nnz,
+                "indptr.npy ends at {start}, but there are {} entries",
+                self.nnz
+            );
+            return Ok(false);
+        }
+        let end = read_index(&mut self.indptr, self.indptr_width)?;
+        ensure!(
+            (start..=self.nnz).contains(&end),
+            "entry {} of indptr.npy is {end}, which isn't in {start}..={}",
+            self.row + 1,
+            self.nnz
+        );
+        indices.clear();
+        for position in start..end {
+            let index = read_index(&mut self.indices, self.index_width)?;
+            ensure!(
+                index < minor,
+                "entry {position} of indices.npy is {index}, but there are only {minor}"
+            );
+            indices.push(index);
+        }
+        values.resize(((end - start) as usize) * self.elem_size, 0);
+        self.data.read_exact(values)?;
+        self.row += 1;
+        self.end = Some(end);
+        Ok(true)
+    }
+}
+
+/// Convert `input` from CSR to CSC or back into `output_dir`, which is the
+/// transpose of its arrays, with bounded memory.
+///
+/// Like [`buffered_disk_io_solution`](crate::strategies::buffered_disk_io_solution)
+/// it goes over the input once per `tiling.pass_cols` output rows (CSR) or
+/// columns (CSC). Each pass counts their entries, prefix-sums the counts
+/// into where they start, and scatters entries into a buffered segment per
+/// output row, written out every `tiling.band_rows` entries. The indices
+/// come out sorted, and the dtypes stay the same.
+pub fn convert(
+    input: &Compressed,
+    output_dir: &Path,
+    tiling: Tiling,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(Compressed, PhaseTimings)> {
+    let (major, minor) = input.axes();
+    let nnz = input.nnz();
+    let segment_len = tiling.band_rows;
+    let pass_len = tiling.pass_cols(minor as usize) as u64;
+    ensure!(
+        segment_len > 0 && (pass_len > 0 || minor == 0),
+        "segments and passes need at least one entry"
+    );
+    ensure!(
+        major == 0 || major - 1 <= input.indices.max_index(),
+        "{} can't hold the {major} indices of the converted matrix",
+        input.indices.header.descr
+    );
+    // the output arrays have the same names, so they'd truncate the input
+    if output_dir.exists() {
+        ensure!(
+            output_dir.canonicalize()? != input.dir.canonicalize()?,
+            "can't convert {} into itself",
+            input.dir.display()
+        );
+    }
+    let mut timings = PhaseTimings::default();
+    let (output, indptr, indices, data) = timings.time(Phase::Setup, || -> Result<_> {
+        create_dir_all(output_dir)
+            .with_context(|| format!("couldn't create {}", output_dir.display()))?;
+        let (indptr_array, indptr) =
+            Array::create(output_dir, "indptr", &input.indptr.header.descr, minor + 1)?;
+        let (indices_array, indices) =
+            Array::create(output_dir, "indices", &input.indices.header.descr, nnz)?;
+        let (data_array, data) = Array::create(output_dir, "data", &input.data.header.descr, nnz)?;
+        write_shape(output_dir, input.shape)?;
+        write_format(output_dir, input.format.other())?;
+        let output = Compressed {
+            dir: output_dir.to_path_buf(),
+            format: input.format.other(),
+            shape: input.shape,
+            indptr: indptr_array,
+            indices: indices_array,
+            data: data_array,
+        };
+        Ok((output, indptr, indices, data))
+    })?;
+    let indptr_width = output.indptr.elem_size;
+    let index_width = output.indices.elem_size;
+    let elem_size = output.data.elem_size;
+    let (mut row_indices, mut row_values) = (Vec::new(), Vec::new());
+
+    let passes = minor.div_ceil(pass_len.max(1));
+    progress.start(
+        "convert",
+        major * passes * 2,
+        input.data.len * elem_size as u64 * passes,
+    );
+    // where the current pass's output rows start
+    let mut pass_start = 0u64;
+    for first in (0..minor).step_by(pass_len.max(1) as usize) {
+        let len = pass_len.min(minor - first) as usize;
+        let in_pass = |index: u64| (first..first + len as u64).contains(&index);
+
+        // count
+        let mut counts = vec![0u64; len];
+        let mut rows = input.rows()?;
+        loop {
+            cancel.check()?;
+            if !timings.time(Phase::Read, || rows.next(&mut row_indices, &mut row_values))? {
+                break;
+            }
+            timings.time(Phase::Transpose, || {
+                for &index in row_indices.iter().filter(|&&index| in_pass(index)) {
+                    counts[(index - first) as usize] += 1;
+                }
+            });
+            progress.advance(1, 0);
+        }
+
+        // prefix-sum
+        let mut starts = Vec::with_capacity(len);
+        let mut next_start = pass_start;
+        let mut indptr_bytes = Vec::with_capacity(len * indptr_width);
+        for count in &counts {
+            starts.push(next_start);
+            indptr_bytes.extend_from_slice(&next_start.to_le_bytes()[..indptr_width]);
+            next_start += count;
+        }
+        timings.time(Phase::Write, || {
+            output.indptr.write_at(&indptr, &indptr_bytes, first)
+        })?;
+
+        // scatter
+        let mut index_segments: Vec<Vec<u8>> =
+            vec![Vec::with_capacity(segment_len * index_width); len];
+        let mut value_segments: Vec<Vec<u8>> =
+            vec![Vec::with_capacity(segment_len * elem_size); len];
+        let mut write_segment = |column: usize,
+                                 index_segment: &mut Vec<u8>,
+                                 value_segment: &mut Vec<u8>|
+         -> std::io::Result<()> {
+            output
+                .indices
+                .write_at(&indices, index_segment, starts[column])?;
+            output.data.write_at(&data, value_segment, starts[column])?;
+            starts[column] += (index_segment.len() / index_width) as u64;
+            index_segment.clear();
+            value_segment.clear();
+            Ok(())
+        };
+        let mut rows = input.rows()?;
+        let mut row = 0u64;
+        loop {
+            cancel.check()?;
+            if !timings.time(Phase::Read, || rows.next(&mut row_indices, &mut row_values))? {
+                break;
+            }
+            let full = timings.time(Phase::Transpose, || {
+                let mut full = Vec::new();
+                for (&index, value) in row_indices.iter().zip(row_values.chunks_exact(elem_size)) {
+                    if !in_pass(index) {
+                        continue;
+                    }
+                    let column = (index - first) as usize;
+                    index_segments[column].extend_from_slice(&row.to_le_bytes()[..index_width]);
+                    value_segments[column].extend_from_slice(value);
+                    if value_segments[column].len() == segment_len * elem_size {
+                        full.push(column);
+                    }
+                }
+                full
+            });
+            timings.time(Phase::Write, || -> std::io::Result<()> {
+                for column in full {
+                    write_segment(
+                        column,
+                        &mut index_segments[column],
+                        &mut value_segments[column],
+                    )?;
+                }
+                Ok(())
+            })?;
+            progress.advance(1, row_values.len() as u64);
+            row += 1;
+        }
+        timings.time(Phase::Write, || -> std::io::Result<()> {
+            for (column, (index_segment, value_segment)) in index_segments
+                .iter_mut()
+                .zip(value_segments.iter_mut())
+                .enumerate()
+            {
+                write_segment(column, index_segment, value_segment)?;
+            }
+            Ok(())
+        })?;
+        pass_start = next_start;
+    }
+    progress.finish();
+    debug_assert_eq!(pass_start, nnz);
+    timings.time(Phase::Write, || {
+        output
+            .indptr
+            .write_at(&indptr, &nnz.to_le_bytes()[..indptr_width], minor)
+    })?;
+    timings.time(Phase::Flush, || -> std::io::Result<()> {
+        for file in [&indptr, &indices, &data] {
+            file.sync_all()?;
+        }
+        Ok(())
+    })?;
+    Ok((output, timings))
+}
+
+/// Write `shape.npy` in `dir`, as scipy does.
+fn write_shape(dir: &Path, shape: [u64; 2]) -> Result<()> {
+    let (array, file) = Array::create(dir, "shape", "<i8", 2)?;
+    let bytes: Vec<u8> = shape.iter().flat_map(|len| len.to_le_bytes()).collect();
+    array.write_at(&file, &bytes, 0)?;
+    Ok(())
+}
+
+/// Write `format.npy` in `dir`, as scipy does: a 0-d unicode string.
+fn write_format(dir: &Path, format: Format) -> Result<()> {
+    let header = NpyHeader {
+        descr: "<U3".to_string(),
+        fortran_order: false,
+        shape: Vec::new(),
+    };
+    let mut file = File::create(dir.join("format.npy"))?;
+    file.write_all(&header.to_bytes())?;
+    for char in format.name().chars() {
+        file.write_all(&(char as u32).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// The first thing wrong with a converted matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConvertMismatch {
+    /// it's in the same format, or has another shape
+    Shape,
+    /// the indices of this output row (CSR) or column (CSC) aren't sorted
+    Unsorted { row: u64 },
+    /// the entries aren't the same
+    Entries,
+}
+
+impl fmt::Display for ConvertMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertMismatch::Shape => write!(f, "the format or shape is wrong"),
+            ConvertMismatch::Unsorted { row } => {
+                write!(f, "the indices of row {row} of the arrays aren't sorted")
+            }
+            ConvertMismatch::Entries => write!(f, "the entries aren't the same"),
+        }
+    }
+}
+
+/// Check that `output` is `input` in the other format, with sorted indices,
+/// in one sequential pass over each.
+///
+/// Entries are compared by a sum of their digests, which doesn't depend on
+/// their order and holds nothing but the sum in memory.
+pub fn verify_converted(
+    input: &Compressed,
+    output: &Compressed,
+) -> Result<Option<ConvertMismatch>> {
+    if output.format != input.format.other() || output.shape != input.shape {
+        return Ok(Some(ConvertMismatch::Shape));
+    }
+    let digest = |matrix: &Compressed, sorted: bool| -> Result<Result<u64, u64>> {
+        let (mut indices, mut values) = (Vec::new(), Vec::new());
+        let mut rows = matrix.rows()?;
+        let mut sum = 0u64;
+        let mut row = 0;
+        while rows.next(&mut indices, &mut values)? {
+            if sorted && !indices.is_sorted() {
+                return Ok(Err(row));
+            }
+            for (&index, value) in indices.iter().zip(values.chunks_exact(matrix.elem_size())) {
+                // where it is in the matrix, whichever the format
+                let (row, col) = match matrix.format {
+                    Format::Csr => (row, index),
+                    Format::Csc => (index, row),
+                };
+                let value = value.iter().copied().fold(0, fnv1a);
+                sum = sum.wrapping_add(hash(hash(row, col), value));
+            }
+            row += 1;
+        }
+        Ok(Ok(sum))
+    };
+    let expected = digest(input, false)?.expect("unsorted input is fine");
+    Ok(match digest(output, true)? {
+        Err(row) => Some(ConvertMismatch::Unsorted { row }),
+        Ok(actual) => (actual != expected).then_some(ConvertMismatch::Entries),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::Silent;
+
+    /// Write a `rows`x`cols` CSR matrix with `<i4` indices and `<f8` values.
+    fn write_csr(dir: &Path, cols: u64, indptr: &[u64], indices: &[u64], values: &[f64]) {
+        create_dir_all(dir).unwrap();
+        let write = |name, descr, bytes: Vec<u8>, len| {
+            let (array, file) = Array::create(dir, name, descr, len).unwrap();
+            array.write_at(&file, &bytes, 0).unwrap();
+        };
+        let int32 = |values: &[u64]| {
+            values
+                .iter()
+                .flat_map(|v| (*v as i32).to_le_bytes())
+                .collect()
+        };
+        write("indptr", "<i4", int32(indptr), indptr.len() as u64);
+        write("indices", "<i4", int32(indices), indices.len() as u64);
+        let values_bytes = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        write("data", "<f8", values_bytes, values.len() as u64);
+        write_shape(dir, [indptr.len() as u64 - 1, cols]).unwrap();
+    }
+
+    /// Every array of `matrix` as numbers.
+    fn arrays(matrix: &Compressed) -> (Vec<u64>, Vec<u64>, Vec<f64>) {
+        let read = |array: &Array| {
+            let mut bytes = Vec::new();
+            array.reader().unwrap().read_to_end(&mut bytes).unwrap();
+            bytes
+        };
+        let ints = |bytes: Vec<u8>| -> Vec<u64> {
+            bytes
+                .chunks_exact(4)
+                .map(|int| i32::from_le_bytes(int.try_into().unwrap()) as u64)
+                .collect()
+        };
+        let floats = read(&matrix.data)
+            .chunks_exact(8)
+            .map(|float| f64::from_le_bytes(float.try_into().unwrap()))
+            .collect();
+        (
+            ints(read(&matrix.indptr)),
+            ints(read(&matrix.indices)),
+            floats,
+        )
+    }
+
+    #[test]
+    fn converts_in_passes_and_segments() {
+        let dir = std::env::temp_dir().join("matrix_transposer_sparse");
+        // [[1, 0, 2, 0],
+        //  [0, 0, 3, 0],
+        //  [4, 5, 0, 6]], with the last row's indices unsorted
+        write_csr(
+            &dir.join("csr"),
+            4,
+            &[0, 2, 3, 6],
+            &[0, 2, 2, 3, 0, 1],
+            &[1.0, 2.0, 3.0, 6.0, 4.0, 5.0],
+        );
+        let csr = Compressed::open(&dir.join("csr")).unwrap();
+        assert_eq!((csr.format, csr.shape, csr.nnz()), (Format::Csr, [3, 4], 6));
+        for (band_rows, pass_cols) in [(1024, None), (1, Some(1)), (2, Some(3))] {
+            let tiling = Tiling {
+                band_rows,
+                pass_cols,
+            };
+            let (csc, _) = convert(
+                &csr,
+                &dir.join("csc"),
+                tiling,
+                &Silent,
+                &Cancellation::new(),
+            )
+            .unwrap();
+            assert_eq!(Compressed::open(&dir.join("csc")).unwrap(), csc);
+            assert_eq!(csc.format, Format::Csc);
+            assert_eq!(
+                arrays(&csc),
+                (
+                    vec![0, 2, 3, 5, 6],
+                    vec![0, 2, 2, 0, 1, 2],
+                    vec![1.0, 4.0, 5.0, 2.0, 3.0, 6.0]
+                )
+            );
+            assert_eq!(verify_converted(&csr, &csc).unwrap(), None);
+
+            // and back, sorted this time
+            let (back, _) = convert(
+                &csc,
+                &dir.join("back"),
+                tiling,
+                &Silent,
+                &Cancellation::new(),
+            )
+            .unwrap();
+            assert_eq!(back.format, Format::Csr);
+            assert_eq!(arrays(&back).1, [0, 2, 2, 0, 1, 3]);
+            assert_eq!(verify_converted(&csc, &back).unwrap(), None);
+            assert_eq!(
+                verify_converted(&csr, &back).unwrap(),
+                Some(ConvertMismatch::Shape)
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_arrays_that_disagree() {
+        let dir = std::env::temp_dir().join("matrix_transposer_sparse_bad");
+        let convert_to_csc = |indptr: &[u64], indices: &[u64]| {
+            write_csr(
+                &dir.join("csr"),
+                2,
+                indptr,
+                indices,
+                &vec![1.0; indices.len()],
+            );
+            let csr = Compressed::open(&dir.join("csr"))?;
+            convert(
+                &csr,
+                &dir.join("csc"),
+                Tiling::default(),
+                &Silent,
+                &Cancellation::new(),
+            )
+            .map(|_| ())
+        };
+        let error = |indptr, indices| format!("{:#}", convert_to_csc(indptr, indices).unwrap_err());
+        assert_eq!(
+            error(&[0, 1, 2], &[0, 2]),
+            "entry 1 of indices.npy is 2, but there are only 2"
+        );
+        assert_eq!(
+            error(&[0, 2, 1], &[0, 1]),
+            "entry 2 of indptr.npy is 1, which isn't in 2..=2"
+        );
+        assert_eq!(
+            error(&[0, 1], &[0, 1]),
+            "indptr.npy ends at 1, but there are 2 entries"
+        );
+        assert_eq!(
+            format!(
+                "{:#}",
+                convert(
+                    &Compressed::open(&dir.join("csr")).unwrap(),
+                    &dir.join("csr").join("."),
+                    Tiling::default(),
+                    &Silent,
+                    &Cancellation::new(),
+                )
+                .unwrap_err()
+            ),
+            format!("can't convert {} into itself", dir.join("csr").display())
+        );
+        // and the input is still there
+        Compressed::open(&dir.join("csr")).unwrap();
+        write_shape(&dir.join("csr"), [u64::MAX, 2]).unwrap();
+        assert_eq!(
+            format!("{:#}", Compressed::open(&dir.join("csr")).unwrap_err()),
+            format!("shape [{}, 2] is too big", u64::MAX)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,735 @@
+use crate::Dimensions;
+use crate::cancel::Cancellation;
+use crate::checkpoint::{Checkpoints, Journal, JournalHeader};
+use crate::cleanup::WorkFiles;
+use crate::permute;
+use crate::progress::Progress;
+use crate::timing::{Phase, PhaseTimings};
+use crate::verify::Digester;
+use crate::workspace::Workspace;
+use anyhow::{Result, ensure};
+use inline_colorization::*;
+use memmap::{MmapMut, MmapOptions};
+use rayon::prelude::*;
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::prelude::FileExt;
+use std::path::{Path, PathBuf};
+
+/// The tiles the buffered strategy transposes the matrix in: bands of
+/// `band_rows` input rows, and of those `pass_cols` columns at a time.
+///
+/// Each column of a band is one contiguous write of `band_rows` elements,
+/// so taller bands mean fewer, bigger writes. When all columns of a band
+/// don't fit in memory, the matrix is transposed in several passes over the
+/// input, each covering the next `pass_cols` columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tiling {
+    pub band_rows: usize,
+    /// `None` for all columns in a single pass
+    pub pass_cols: Option<usize>,
+}
+
+impl Default for Tiling {
+    fn default() -> Self {
+        Tiling {
+            band_rows: 2usize.pow(10),
+            pass_cols: None,
+        }
+    }
+}
+
+impl Tiling {
+    /// The columns of each pass over a matrix with `cols` columns.
+    pub fn pass_cols(self, cols: usize) -> usize {
+        self.pass_cols.map_or(cols, |pass_cols| pass_cols.min(cols))
+    }
+
+    /// How many passes over the input it takes.
+    pub fn passes(self, cols: usize) -> usize {
+        cols.div_ceil(self.pass_cols(cols).max(1))
+    }
+
+    /// Roughly the bytes of memory the buffered strategy needs for `dims`.
+    pub fn memory(
+        self,
+        Dimensions {
+            cols, elem_size, ..
+        }: Dimensions,
+    ) -> u64 {
+        // the band, the part of a row that goes into it, and the reader
+        (self.band_rows as u64 + 1) * self.pass_cols(cols) as u64 * elem_size as u64
+            + READ_BUFF_SIZE as u64
+    }
+}
+
+/// The read buffer of the buffered strategy.
+const READ_BUFF_SIZE: usize = 30 * 2usize.pow(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    InMemory,
+    Mmap,
+    OnDisk,
+    BufferedOnDisk,
+    Join,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 5] = [
+        Strategy::InMemory,
+        Strategy::Mmap,
+        Strategy::OnDisk,
+        Strategy::BufferedOnDisk,
+        Strategy::Join,
+    ];
+
+    /// A short identifier, e.g. for benchmark ids.
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::InMemory => "in_memory",
+            Strategy::Mmap => "mmap",
+            Strategy::OnDisk => "on_disk",
+            Strategy::BufferedOnDisk => "buffered_on_disk",
+            Strategy::Join => "join",
+        }
+    }
+
+    /// The strategy [named](Self::name) `name`.
+    pub fn from_name(name: &str) -> Option<Strategy> {
+        Strategy::ALL
+            .into_iter()
+            .find(|strategy| strategy.name() == name)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Strategy::InMemory => "in-memory transpose",
+            Strategy::Mmap => "memmap solution",
+            Strategy::OnDisk => "transpose entirely on disk",
+            Strategy::BufferedOnDisk => "transpose on disk but buffered",
+            Strategy::Join => "transpose with temp files",
+        }
+    }
+
+    pub fn color(self) -> &'static str {
+        match self {
+            Strategy::InMemory => color_magenta,
+            Strategy::Mmap => color_yellow,
+            Strategy::OnDisk => color_bright_blue,
+            Strategy::BufferedOnDisk => color_bright_green,
+            Strategy::Join => color_cyan,
+        }
+    }
+
+    /// Whether this can record and resume from [`Checkpoints`].
+    pub fn can_checkpoint(self) -> bool {
+        self == Strategy::BufferedOnDisk
+    }
+
+    /// Whether this can permute the axes of N-d arrays, not just transpose
+    /// matrices.
+    pub fn can_permute(self) -> bool {
+        matches!(
+            self,
+            Strategy::InMemory | Strategy::Mmap | Strategy::BufferedOnDisk
+        )
+    }
+
+    /// Transpose the file at `input_path` into
+    /// [`Workspace::output_path`], with any scratch files in `workspace`'s
+    /// temp dir.
+    ///
+    /// With [`Workspace::atomic_outputs`] the output is written under
+    /// [`Workspace::partial_path`] and only [published](Workspace::publish)
+    /// once it's complete and synced, as part of the flush phase.
+    ///
+    /// [Permuted](Dimensions::permuted) arrays go through
+    /// [`permute`](crate::permute) instead, if this [can](Self::can_permute).
+    pub fn run(
+        self,
+        dims: Dimensions,
+        input_path: &Path,
+        workspace: &Workspace,
+        progress: &dyn Progress,
+        cancel: &Cancellation,
+    ) -> Result<(File, PhaseTimings)> {
+        self.run_with(dims, input_path, workspace, None, progress, cancel)
+    }
+
+    /// [`run`](Self::run), feeding the input to `digester` as it's read.
+    ///
+    /// A run that doesn't read all of the input in order, like one resumed
+    /// from a checkpoint, leaves `digester` short, so it doesn't
+    /// [finish](Digester::finish).
+    pub fn run_digesting(
+        self,
+        dims: Dimensions,
+        input_path: &Path,
+        workspace: &Workspace,
+        digester: &mut Digester,
+        progress: &dyn Progress,
+        cancel: &Cancellation,
+    ) -> Result<(File, PhaseTimings)> {
+        self.run_with(
+            dims,
+            input_path,
+            workspace,
+            Some(digester),
+            progress,
+            cancel,
+        )
+    }
+
+    fn run_with(
+        self,
+        dims: Dimensions,
+        input_path: &Path,
+        workspace: &Workspace,
+        digester: Option<&mut Digester>,
+        progress: &dyn Progress,
+        cancel: &Cancellation,
+    ) -> Result<(File, PhaseTimings)> {
+        ensure!(
+            workspace.checkpoints == Checkpoints::Off || self.can_checkpoint(),
+            "{} can't checkpoint",
+            self.name()
+        );
+        let output_path = workspace.output_path(self);
+        let write_path = if workspace.atomic_outputs {
+            workspace.partial_path(self)
+        } else {
+            output_path.clone()
+        };
+        ensure!(
+            dims.permuted.is_none() || self.can_permute(),
+            "{} only transposes matrices, not other permutations of axes",
+            self.name()
+        );
+        let (output_file, mut timings) = match (self, &dims.permuted) {
+            (Strategy::InMemory, Some(axes)) => {
+                permute::in_memory(dims, axes, input_path, &write_path, progress, cancel)
+            }
+            (Strategy::Mmap, Some(axes)) => {
+                permute::mmap(dims, axes, input_path, &write_path, progress, cancel)
+            }
+            #[cfg(unix)]
+            (Strategy::BufferedOnDisk, Some(axes)) => {
+                ensure!(
+                    workspace.checkpoints == Checkpoints::Off,
+                    "can't checkpoint a permute of axes"
+                );
+                permute::buffered_on_disk(
+                    dims,
+                    axes,
+                    input_path,
+                    &write_path,
+                    workspace.tiling,
+                    progress,
+                    cancel,
+                )
+            }
+            (Strategy::InMemory, None) => {
+                in_memory(dims, input_path, &write_path, digester, progress, cancel)
+            }
+            (Strategy::Mmap, None) => {
+                mmap_solution(dims, input_path, &write_path, digester, progress, cancel)
+            }
+            #[cfg(unix)]
+            (Strategy::OnDisk, None) => {
+                disk_io_solution(dims, input_path, &write_path, digester, progress, cancel)
+            }
+            #[cfg(unix)]
+            (Strategy::BufferedOnDisk, None) => buffered_disk_io_solution(
+                dims,
+                input_path,
+                &write_path,
+                digester,
+                workspace.tiling,
+                workspace.checkpoints,
+                progress,
+                cancel,
+            ),
+            #[cfg(not(unix))]
+            (Strategy::OnDisk | Strategy::BufferedOnDisk, _) => {
+                anyhow::bail!("function not available on non-unix systems")
+            }
+            (Strategy::Join, None) => join_file_handles(
+                dims,
+                input_path,
+                &write_path,
+                digester,
+                &workspace.scratch_dir("transpose_columns"),
+                progress,
+                cancel,
+            ),
+            (Strategy::OnDisk | Strategy::Join, Some(_)) => {
+                unreachable!("only strategies that can permute get here")
+            }
+        }?;
+        if workspace.atomic_outputs {
+            timings.time(Phase::Flush, || {
+                workspace.publish(&write_path, &output_path)
+            })?;
+        }
+        Ok((output_file, timings))
+    }
+}
+
+pub fn in_memory(
+    dims @ Dimensions {
+        size,
+        rows,
+        cols,
+        elem_size,
+        ..
+    }: Dimensions,
+    input_path: &Path,
+    output_path: &Path,
+    digester: Option<&mut Digester>,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, PhaseTimings)> {
+    let mut timings = PhaseTimings::default();
+    let (mut input_file, mut output_file) = timings.time(Phase::Setup, || -> Result<_> {
+        let input_file = dims.open_input(input_path)?;
+        let output_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output_path)?;
+        Ok((input_file, output_file))
+    })?;
+
+    let mut input_buff = Vec::with_capacity(size as usize);
+    let num_read_bytes = timings.time(Phase::Read, || input_file.read_to_end(&mut input_buff))?;
+    assert_eq!(num_read_bytes, size as usize);
+    if let Some(digester) = digester {
+        timings.time(Phase::Read, || digester.feed(0, 0, &input_buff));
+    }
+
+    // transpose the data in memory
+    progress.start("transpose", rows as u64, size);
+    let output_buff = timings.time(Phase::Transpose, || -> Result<_> {
+        let mut output_buff = vec![0; size as usize];
+        for i in 0..rows {
+            cancel.check()?;
+            for j in 0..cols {
+                let (from, to) = ((i * cols + j) * elem_size, (j * rows + i) * elem_size);
+                output_buff[to..to + elem_size]
+                    .copy_from_slice(&input_buff[from..from + elem_size]);
+            }
+            progress.advance(1, (cols * elem_size) as u64);
+        }
+        Ok(output_buff)
+    })?;
+    progress.finish();
+
+    timings.time(Phase::Write, || output_file.write_all(&output_buff))?;
+    timings.time(Phase::Flush, || {
+        output_file.flush()?;
+        output_file.sync_all()
+    })?;
+
+    Ok((output_file, timings))
+}
+
+pub fn mmap_solution(
+    dims @ Dimensions {
+        rows,
+        cols,
+        size,
+        elem_size,
+        ..
+    }: Dimensions,
+    input_path: &Path,
+    output_path: &Path,
+    mut digester: Option<&mut Digester>,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, PhaseTimings)> {
+    let mut timings = PhaseTimings::default();
+    let (output_file, input_mmap, mut output_mmap) =
+        timings.time(Phase::Setup, || -> Result<_> {
+            let input_file = File::open(input_path)?;
+            let output_file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(output_path)?;
+            output_file.set_len(size)?;
+            let input_mmap = unsafe {
+                MmapOptions::new()
+                    .offset(dims.input_offset)
+                    .map(&input_file)?
+            };
+            let output_mmap = unsafe { MmapMut::map_mut(&output_file)? };
+            Ok((output_file, input_mmap, output_mmap))
+        })?;
+
+    // reads and writes are page faults inside the loop, so they can't be
+    // told apart from the transpose itself
+    progress.start("transpose", rows as u64, size);
+    timings.time(Phase::Transpose, || -> Result<()> {
+        for i in 0..rows {
+            cancel.check()?;
+            for j in 0..cols {
+                let (from, to) = ((i * cols + j) * elem_size, (j * rows + i) * elem_size);
+                output_mmap[to..to + elem_size]
+                    .copy_from_slice(&input_mmap[from..from + elem_size]);
+            }
+            if let Some(digester) = digester.as_deref_mut() {
+                let row_len = cols * elem_size;
+                digester.feed(i, 0, &input_mmap[i * row_len..(i + 1) * row_len]);
+            }
+            progress.advance(1, (cols * elem_size) as u64);
+        }
+        Ok(())
+    })?;
+    progress.finish();
+
+    timings.time(Phase::Flush, || {
+        output_mmap.flush()?;
+        output_file.sync_all()
+    })?;
+
+    Ok((output_file, timings))
+}
+
+#[cfg(unix)]
+pub fn disk_io_solution(
+    Dimensions {
+        rows,
+        cols,
+        size,
+        elem_size,
+        input_offset,
+        ..
+    }: Dimensions,
+    input_path: &Path,
+    output_path: &Path,
+    mut digester: Option<&mut Digester>,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, PhaseTimings)> {
+    let mut timings = PhaseTimings::default();
+    let (input_file, mut output_file) = timings.time(Phase::Setup, || -> Result<_> {
+        let input_file = File::open(input_path)?;
+        let output_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(output_path)?;
+        output_file.set_len(size)?;
+        Ok((input_file, output_file))
+    })?;
+
+    // every element is written straight to its transposed position, so the
+    // transpose happens as part of the write phase
+    let mut input_row_buf = vec![0u8; cols * elem_size];
+    progress.start("transpose", rows as u64, size);
+    for i in 0..rows {
+        cancel.check()?;
+        timings.time(Phase::Read, || -> std::io::Result<()> {
+            input_file.read_exact_at(
+                &mut input_row_buf,
+                input_offset + (i * cols * elem_size) as u64,
+            )?;
+            if let Some(digester) = digester.as_deref_mut() {
+                digester.feed(i, 0, &input_row_buf);
+            }
+            Ok(())
+        })?;
+        timings.time(Phase::Write, || {
+            input_row_buf
+                .chunks_exact(elem_size)
+                .enumerate()
+                .try_for_each(|(j, element)| {
+                    output_file.write_all_at(element, ((j * rows + i) * elem_size) as u64)
+                })
+        })?;
+        progress.advance(1, (cols * elem_size) as u64);
+    }
+    progress.finish();
+
+    timings.time(Phase::Flush, || {
+        output_file.flush()?;
+        output_file.sync_all()
+    })?;
+
+    Ok((output_file, timings))
+}
+
+#[cfg(unix)]
+#[allow(clippy::too_many_arguments)]
+pub fn buffered_disk_io_solution(
+    dims @ Dimensions {
+        rows,
+        cols,
+        size,
+        elem_size,
+        ..
+    }: Dimensions,
+    input_path: &Path,
+    output_path: &Path,
+    mut digester: Option<&mut Digester>,
+    tiling: Tiling,
+    checkpoints: Checkpoints,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, PhaseTimings)> {
+    let Tiling { band_rows, .. } = tiling;
+    let pass_cols = tiling.pass_cols(cols);
+    ensure!(
+        band_rows > 0 && pass_cols > 0,
+        "tiles need at least one row and column"
+    );
+    ensure!(
+        checkpoints == Checkpoints::Off || pass_cols == cols,
+        "can't checkpoint a transpose in several passes"
+    );
+    let mut timings = PhaseTimings::default();
+    let (mut input_file_reader, mut output_file, mut journal, start_row) =
+        timings.time(Phase::Setup, || -> Result<_> {
+            let journal_path = Journal::path_for(output_path);
+            let header = JournalHeader::new(Strategy::BufferedOnDisk, dims, input_path)?;
+            let (journal, start_row) = match checkpoints {
+                Checkpoints::Off => (None, 0),
+                Checkpoints::Record => (Some(Journal::create(&journal_path, &header)?), 0),
+                Checkpoints::Resume => {
+                    match Journal::resume(&journal_path, &header, output_path)? {
+                        Some((journal, start_row)) => {
+                            println!("resuming from row {start_row} of {rows}");
+                            (Some(journal), start_row)
+                        }
+                        None => (Some(Journal::create(&journal_path, &header)?), 0),
+                    }
+                }
+            };
+            let input_file_reader =
+                BufReader::with_capacity(READ_BUFF_SIZE, dims.open_input(input_path)?);
+            let output_file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                // what's already there is what's being resumed
+                .truncate(start_row == 0)
+                .open(output_path)?;
+            output_file.set_len(size)?;
+            Ok((input_file_reader, output_file, journal, start_row))
+        })?;
+
+    let mut output_buff_buff: Vec<Vec<u8>> =
+        vec![Vec::with_capacity(band_rows * elem_size); pass_cols];
+    let mut input_row_buff = vec![0; pass_cols * elem_size];
+    let row_len = (cols * elem_size) as u64;
+    progress.start("transpose", (rows * tiling.passes(cols)) as u64, size);
+    progress.advance(start_row as u64, start_row as u64 * row_len);
+
+    for first_col in (0..cols).step_by(pass_cols) {
+        let pass_len = pass_cols.min(cols - first_col);
+        let input_row_buff = &mut input_row_buff[..pass_len * elem_size];
+        let output_buff_buff = &mut output_buff_buff[..pass_len];
+        // the rest of the row, up to where this pass starts in the next one
+        let skip = row_len as i64 - input_row_buff.len() as i64;
+        timings.time(Phase::Read, || {
+            input_file_reader.seek(SeekFrom::Start(
+                dims.input_offset + start_row as u64 * row_len + (first_col * elem_size) as u64,
+            ))
+        })?;
+        let mut write_index = start_row;
+        for row_index in start_row..rows {
+            cancel.check()?;
+            timings.time(Phase::Read, || -> std::io::Result<()> {
+                input_file_reader.read_exact(input_row_buff)?;
+                if skip > 0 && row_index != rows - 1 {
+                    input_file_reader.seek_relative(skip)?;
+                }
+                if let Some(digester) = digester.as_deref_mut() {
+                    digester.feed(row_index, first_col, input_row_buff);
+                }
+                Ok(())
+            })?;
+            timings.time(Phase::Transpose, || {
+                output_buff_buff
+                    .par_iter_mut()
+                    .zip(input_row_buff.par_chunks_exact(elem_size))
+                    .for_each(|(row_buf, row_entry)| row_buf.extend_from_slice(row_entry))
+            });
+            if output_buff_buff.first().unwrap().len() >= band_rows * elem_size
+                || row_index == rows - 1
+            {
+                timings.time(Phase::Write, || {
+                    output_buff_buff.par_iter().enumerate().try_for_each(
+                        |(column_index, col_buf)| {
+                            output_file.write_all_at(
+                                col_buf,
+                                ((write_index + (first_col + column_index) * rows) * elem_size)
+                                    as u64,
+                            )
+                        },
+                    )
+                })?;
+                output_buff_buff
+                    .par_iter_mut()
+                    .for_each(|row_buf| row_buf.clear());
+                write_index = row_index + 1;
+                if let Some(journal) = &mut journal {
+                    timings.time(Phase::Flush, || -> Result<()> {
+                        output_file.sync_data()?;
+                        journal.record(write_index)
+                    })?;
+                }
+            }
+            progress.advance(1, (pass_len * elem_size) as u64);
+        }
+    }
+    progress.finish();
+
+    timings.time(Phase::Flush, || {
+        output_file.flush()?;
+        output_file.sync_all()
+    })?;
+    if let Some(journal) = journal {
+        timings.time(Phase::Cleanup, || journal.finish())?;
+    }
+
+    Ok((output_file, timings))
+}
+
+pub fn join_file_handles(
+    dims @ Dimensions { size, cols, .. }: Dimensions,
+    input_path: &Path,
+    output_path: &Path,
+    digester: Option<&mut Digester>,
+    temp_dir: &Path,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<(File, PhaseTimings)> {
+    let mut timings = PhaseTimings::default();
+    // the temp files go even with -k, they're only half of the output
+    let mut temp_files = WorkFiles::new(false);
+    let temp_file_names: Vec<PathBuf> = (0..cols)
+        .map(|i| {
+            temp_files
+                .track(temp_dir.join(format!("row-{}.md", i)))
+                .to_path_buf()
+        })
+        .collect();
+
+    let io_result = splice_into_temp_files(
+        dims,
+        input_path,
+        output_path,
+        digester,
+        &temp_file_names,
+        &mut timings,
+        progress,
+        cancel,
+    );
+
+    let delete_result = timings.time(Phase::Cleanup, || {
+        let result = temp_files.remove_all();
+        // only goes if it's empty, and it doesn't matter if it stays
+        let _ = std::fs::remove_dir(temp_dir);
+        result
+    });
+
+    let output_file = delete_result.and(io_result)?;
+    assert_eq!(output_file.metadata()?.len(), size);
+    Ok((output_file, timings))
+}
+
+/// The fallible part of [`join_file_handles`], split out so the temp files
+/// get cleaned up in a timed phase no matter where this fails.
+#[allow(clippy::too_many_arguments)]
+fn splice_into_temp_files(
+    dims @ Dimensions {
+        size,
+        rows,
+        cols,
+        elem_size,
+        ..
+    }: Dimensions,
+    input_path: &Path,
+    output_path: &Path,
+    mut digester: Option<&mut Digester>,
+    temp_file_names: &[PathBuf],
+    timings: &mut PhaseTimings,
+    progress: &dyn Progress,
+    cancel: &Cancellation,
+) -> Result<File> {
+    let (mut input_handle, mut output_file, mut new_row_file_handles) =
+        timings.time(Phase::Setup, || -> Result<_> {
+            create_dir_all(temp_file_names[0].parent().unwrap())?;
+            let input_handle = BufReader::new(dims.open_input(input_path)?);
+            let output_file = OpenOptions::new()
+                .write(true)
+                .read(true)
+                .create(true)
+                .truncate(true)
+                .open(output_path)?;
+            output_file.set_len(size)?;
+            // one file per output row, i.e. per input column
+            let new_row_file_handles = temp_file_names
+                .iter()
+                .map(|temp_file_name| {
+                    let temp_file_handle = OpenOptions::new()
+                        .write(true)
+                        .read(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(temp_file_name)?;
+                    let temp_file_buff_writer = BufWriter::new(temp_file_handle);
+                    Ok((temp_file_name, temp_file_buff_writer))
+                })
+                .collect::<Result<Vec<(&PathBuf, BufWriter<File>)>>>()?;
+            Ok((input_handle, output_file, new_row_file_handles))
+        })?;
+
+    let mut row_buf = vec![0u8; cols * elem_size];
+    progress.start("split", rows as u64, size);
+    // read in row by row and splice them into separate column files
+    for row in 0..rows {
+        cancel.check()?;
+        timings.time(Phase::Read, || -> std::io::Result<()> {
+            input_handle.read_exact(&mut row_buf)?;
+            if let Some(digester) = digester.as_deref_mut() {
+                digester.feed(row, 0, &row_buf);
+            }
+            Ok(())
+        })?;
+        timings.time(Phase::Transpose, || {
+            row_buf
+                .par_chunks_exact(elem_size)
+                .zip(&mut new_row_file_handles)
+                .try_for_each(|(element, output_row)| output_row.1.write_all(element))
+        })?;
+        progress.advance(1, (cols * elem_size) as u64);
+    }
+    progress.finish();
+
+    //concatenate each column file into one base file
+    progress.start("concatenate", cols as u64, size);
+    timings.time(Phase::Write, || {
+        new_row_file_handles
+            .into_iter()
+            .try_for_each(|(handle, mut writer)| -> Result<()> {
+                cancel.check()?;
+                writer.flush()?; // ensure the writer is actually written out
+                let copied = std::io::copy(&mut File::open(handle)?, &mut output_file)?;
+                progress.advance(1, copied);
+                Ok(())
+            })
+    })?;
+    progress.finish();
+
+    timings.time(Phase::Flush, || {
+        output_file.flush()?;
+        output_file.sync_all()
+    })?;
+    Ok(output_file)
+}
@@ -0,0 +1,145 @@
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::{AddAssign, Div};
+use std::time::{Duration, Instant};
+
+/// The named phases every strategy reports timings for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// opening files, `set_len`, creating maps or temp files
+    Setup,
+    /// pulling the input off of disk
+    Read,
+    /// moving elements into their transposed position
+    Transpose,
+    /// handing the transposed data to the OS
+    Write,
+    /// `flush`/`fsync` until the output is durable
+    Flush,
+    /// deleting temp files and other leftovers
+    Cleanup,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [
+        Phase::Setup,
+        Phase::Read,
+        Phase::Transpose,
+        Phase::Write,
+        Phase::Flush,
+        Phase::Cleanup,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Setup => "setup",
+            Phase::Read => "read",
+            Phase::Transpose => "transpose",
+            Phase::Write => "write",
+            Phase::Flush => "flush",
+            Phase::Cleanup => "cleanup",
+        }
+    }
+
+    /// Whether this phase counts toward the headline time of a strategy.
+    ///
+    /// The headline is the time it takes to get the data from the input file
+    /// into a durable, transposed output file: read, transpose, write and
+    /// flush. Setup and cleanup are reported but never counted, for every
+    /// strategy, so that e.g. creating the mmaps or deleting the temp files
+    /// of the join strategy doesn't skew the comparison either way.
+    pub fn counts_toward_headline(self) -> bool {
+        !matches!(self, Phase::Setup | Phase::Cleanup)
+    }
+
+    pub fn from_name(name: &str) -> Option<Phase> {
+        Phase::ALL.into_iter().find(|phase| phase.name() == name)
+    }
+}
+
+/// Accumulated wall-clock time per [`Phase`].
+///
+/// Serialized as a map from phase name to seconds, so stored results stay
+/// readable and don't depend on the order of [`Phase::ALL`].
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(into = "BTreeMap<String, f64>", try_from = "BTreeMap<String, f64>")]
+pub struct PhaseTimings {
+    durations: [Duration; Phase::ALL.len()],
+}
+
+impl PhaseTimings {
+    /// Run `f`, adding its wall-clock time to `phase`.
+    ///
+    /// Phases may be timed any number of times; strategies that interleave
+    /// reading and writing (e.g. row by row) just call this once per row.
+    pub fn time<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    /// Add an externally measured `duration` to `phase`.
+    pub fn record(&mut self, phase: Phase, duration: Duration) {
+        self.durations[phase as usize] += duration;
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        self.durations[phase as usize]
+    }
+
+    /// The sum of all phases that [count toward the headline](Phase::counts_toward_headline).
+    pub fn headline(&self) -> Duration {
+        Phase::ALL
+            .into_iter()
+            .filter(|phase| phase.counts_toward_headline())
+            .map(|phase| self.get(phase))
+            .sum()
+    }
+
+    /// The sum of all phases, counted or not.
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+}
+
+impl AddAssign for PhaseTimings {
+    fn add_assign(&mut self, rhs: Self) {
+        for (lhs, rhs) in self.durations.iter_mut().zip(rhs.durations) {
+            *lhs += rhs;
+        }
+    }
+}
+
+impl Div<u32> for PhaseTimings {
+    type Output = PhaseTimings;
+
+    fn div(self, rhs: u32) -> Self::Output {
+        PhaseTimings {
+            durations: self.durations.map(|duration| duration / rhs),
+        }
+    }
+}
+
+impl From<PhaseTimings> for BTreeMap<String, f64> {
+    fn from(timings: PhaseTimings) -> Self {
+        Phase::ALL
+            .into_iter()
+            .map(|phase| (phase.name().to_string(), timings.get(phase).as_secs_f64()))
+            .collect()
+    }
+}
+
+impl TryFrom<BTreeMap<String, f64>> for PhaseTimings {
+    type Error = anyhow::Error;
+
+    fn try_from(seconds: BTreeMap<String, f64>) -> Result<Self, Self::Error> {
+        let mut timings = PhaseTimings::default();
+        for (name, seconds) in seconds {
+            let phase = Phase::from_name(&name).ok_or_else(|| anyhow!("unknown phase {name:?}"))?;
+            timings.record(phase, Duration::try_from_secs_f64(seconds)?);
+        }
+        Ok(timings)
+    }
+}
@@ -0,0 +1,563 @@
+use crate::Dimensions;
+use anyhow::{Result, ensure};
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::prelude::FileExt;
+
+const CHUNK_SIZE: usize = 2usize.pow(20);
+
+/// How many bytes to show on either side of a mismatch.
+const WINDOW_RADIUS: u64 = 8;
+
+/// The first place a transposed output differs from what it should be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// byte offset into the output
+    pub offset: u64,
+    /// index of the element into the output, e.g. (row, col) of a transposed
+    /// matrix, or into the [collapsed](crate::axes::Axes::collapsed) axes of a
+    /// permuted array
+    pub output_index: Vec<usize>,
+    /// index of the same element into the input
+    pub input_index: Vec<usize>,
+    /// offset of the first byte of `expected` and `actual`
+    pub window_start: u64,
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+impl Mismatch {
+    /// Translate an offset into the transposed output of a `dims` input.
+    pub fn at(dims: Dimensions, offset: u64) -> Self {
+        let (output_shape, permutation) = match &dims.permuted {
+            Some(axes) => (axes.output_shape(), axes.permutation().to_vec()),
+            None => (vec![dims.cols, dims.rows], vec![1, 0]),
+        };
+        let output_index = index_of(offset / dims.elem_size as u64, &output_shape);
+        // output axis `k` is input axis `permutation[k]`
+        let mut input_index = vec![0; permutation.len()];
+        for (&axis, &index) in permutation.iter().zip(&output_index) {
+            input_index[axis] = index;
+        }
+        Mismatch {
+            offset,
+            output_index,
+            input_index,
+            window_start: offset,
+            expected: Vec::new(),
+            actual: Vec::new(),
+        }
+    }
+}
+
+/// The row-major index of `element` into an array of `shape`. The first axis
+/// runs on past its end, so an offset past the last element has one too.
+fn index_of(mut element: u64, shape: &[usize]) -> Vec<usize> {
+    let mut index = vec![0; shape.len()];
+    for (axis, &len) in shape.iter().enumerate().skip(1).rev() {
+        index[axis] = (element % len as u64) as usize;
+        element /= len as u64;
+    }
+    index[0] = element as usize;
+    index
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let marker = (self.offset - self.window_start) as usize;
+        writeln!(
+            f,
+            "first mismatch at byte {}: element {:?} of the output ({:?} of the input)",
+            self.offset, self.output_index, self.input_index
+        )?;
+        writeln!(f, "  bytes from offset {}:", self.window_start)?;
+        writeln!(f, "  expected {}", show_window(&self.expected, marker))?;
+        write!(f, "  actual   {}", show_window(&self.actual, marker))
+    }
+}
+
+/// The bytes of a window, with the mismatching one in brackets.
+fn show_window(bytes: &[u8], marker: usize) -> String {
+    bytes
+        .iter()
+        .enumerate()
+        .map(|(index, byte)| {
+            if index == marker {
+                format!("[{byte:02x}]")
+            } else {
+                format!(" {byte:02x} ")
+            }
+        })
+        .chain((bytes.len() <= marker).then(|| "[EOF]".to_string()))
+        .collect()
+}
+
+/// Check the transposed output of a `dims` input without a reference file,
+/// by computing every expected byte with `input_byte`, which gives the byte
+/// at any offset of the input (e.g. [`pattern_byte`](crate::pattern_byte)).
+///
+/// Streams the output once, so it works no matter how big the matrix is, and
+/// checks [permuted](Dimensions::permuted) arrays the same way.
+pub fn verify_with_oracle(
+    dims @ Dimensions {
+        size, elem_size, ..
+    }: Dimensions,
+    actual: &File,
+    input_byte: impl Fn(u64) -> u8,
+) -> Result<Option<Mismatch>> {
+    let elem_size = elem_size as u64;
+    let expected_byte = |offset: u64| {
+        let (element, byte) = (offset / elem_size, offset % elem_size);
+        input_byte(dims.source(element) * elem_size + byte)
+    };
+    let mut actual_reader = actual;
+    actual_reader.seek(SeekFrom::Start(0))?;
+    let mut actual_buf = vec![0u8; CHUNK_SIZE];
+
+    let mut offset = 0u64;
+    loop {
+        let actual_len = read_full(&mut actual_reader, &mut actual_buf)?;
+        let expected_len = (size - offset).min(CHUNK_SIZE as u64) as usize;
+        let common = expected_len.min(actual_len);
+        let first_difference = actual_buf[..common]
+            .iter()
+            .enumerate()
+            .position(|(index, actual)| *actual != expected_byte(offset + index as u64))
+            .or((expected_len != actual_len).then_some(common));
+        if let Some(index) = first_difference {
+            let mut mismatch = Mismatch::at(dims, offset + index as u64);
+            mismatch.window_start = mismatch.offset.saturating_sub(WINDOW_RADIUS);
+            let window_end = (mismatch.offset + WINDOW_RADIUS + 1).min(size);
+            mismatch.expected = (mismatch.window_start..window_end)
+                .map(expected_byte)
+                .collect();
+            mismatch.actual = read_window(actual, mismatch.window_start)?;
+            return Ok(Some(mismatch));
+        }
+        if expected_len == 0 {
+            return Ok(None);
+        }
+        offset += common as u64;
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Feed one byte into an FNV-1a digest. It's byte-at-a-time, so a digest per
+/// column can be built up while streaming through the rows.
+pub(crate) fn fnv1a(digest: u64, byte: u8) -> u64 {
+    (digest ^ byte as u64).wrapping_mul(FNV_PRIME)
+}
+
+/// A digest of every row and every column of an input matrix, for checking
+/// the transpose of inputs there's no [oracle](verify_with_oracle) for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digests {
+    pub rows: Vec<u64>,
+    pub cols: Vec<u64>,
+}
+
+/// Which parts of a transposed output don't match the [`Digests`] of its
+/// input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DigestMismatch {
+    Length {
+        expected: u64,
+        actual: u64,
+    },
+    Content {
+        /// the first output row that isn't the matching input column
+        first_bad_row: Option<usize>,
+        bad_rows: usize,
+        /// the first output column that isn't the matching input row
+        first_bad_col: Option<usize>,
+        bad_cols: usize,
+    },
+}
+
+impl fmt::Display for DigestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestMismatch::Length { expected, actual } => {
+                write!(f, "output is {actual} bytes but should be {expected}")
+            }
+            DigestMismatch::Content {
+                first_bad_row,
+                bad_rows,
+                first_bad_col,
+                bad_cols,
+            } => {
+                write!(
+                    f,
+                    "{bad_rows} output rows and {bad_cols} output columns are wrong"
+                )?;
+                if let Some(row) = first_bad_row {
+                    write!(f, ", starting with row {row} (column {row} of the input)")?;
+                }
+                if let Some(col) = first_bad_col {
+                    write!(f, " and column {col} (row {col} of the input)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Digests {
+    /// Digest every row and column of `input` in one sequential pass, holding
+    /// only one digest per row and column in memory. Strategies can build
+    /// them up while they read instead, with a [`Digester`].
+    pub fn of_input(dims: Dimensions, input: &File) -> Result<Self> {
+        matrix_only(dims)?;
+        let (rows, cols) = digest_matrix(
+            input,
+            dims.input_offset,
+            dims.size,
+            dims.rows,
+            dims.cols,
+            dims.elem_size,
+        )?;
+        Ok(Digests { rows, cols })
+    }
+
+    /// Check the transposed output of the input these digests were made of,
+    /// in one sequential pass.
+    ///
+    /// Every output row has to match its input column and every output column
+    /// its input row, which also narrows down where a mismatch is.
+    pub fn verify_transposed(
+        &self,
+        Dimensions {
+            size,
+            rows,
+            cols,
+            elem_size,
+            ..
+        }: Dimensions,
+        output: &File,
+    ) -> Result<Option<DigestMismatch>> {
+        let actual = output.metadata()?.len();
+        if actual != size {
+            return Ok(Some(DigestMismatch::Length {
+                expected: size,
+                actual,
+            }));
+        }
+        // the output has `cols` rows of length `rows`
+        let (output_rows, output_cols) = digest_matrix(output, 0, size, cols, rows, elem_size)?;
+        let bad = |actual: &[u64], expected: &[u64]| -> (Option<usize>, usize) {
+            let mut bad = actual
+                .iter()
+                .zip(expected)
+                .enumerate()
+                .filter(|(_, (actual, expected))| actual != expected)
+                .map(|(index, _)| index);
+            let first = bad.next();
+            (first, first.map_or(0, |_| 1 + bad.count()))
+        };
+        let (first_bad_row, bad_rows) = bad(&output_rows, &self.cols);
+        let (first_bad_col, bad_cols) = bad(&output_cols, &self.rows);
+        Ok(
+            (bad_rows > 0 || bad_cols > 0).then_some(DigestMismatch::Content {
+                first_bad_row,
+                bad_rows,
+                first_bad_col,
+                bad_cols,
+            }),
+        )
+    }
+}
+
+/// (row digests, column digests) of a `rows` by `cols` matrix of
+/// `elem_size` byte elements stored in `file`, starting at `start`.
+fn digest_matrix(
+    file: &File,
+    start: u64,
+    size: u64,
+    rows: usize,
+    cols: usize,
+    elem_size: usize,
+) -> Result<(Vec<u64>, Vec<u64>)> {
+    let mut reader = file;
+    reader.seek(SeekFrom::Start(start))?;
+    let mut digester = Digester::of_shape(size, rows, cols, elem_size);
+    // whole elements, so every chunk starts at an element
+    let chunk_size = (CHUNK_SIZE / elem_size).max(1) * elem_size;
+    let mut buf = vec![0u8; chunk_size];
+    let mut offset = 0u64;
+    while offset < size {
+        let len = read_full(
+            &mut reader,
+            &mut buf[..(size - offset).min(chunk_size as u64) as usize],
+        )?;
+        ensure!(len > 0, "file ended after {offset} of {size} bytes");
+        let element = (offset / elem_size as u64) as usize;
+        digester.feed(element / cols, element % cols, &buf[..len]);
+        offset += len as u64;
+    }
+    let digests = digester.digests;
+    Ok((digests.rows, digests.cols))
+}
+
+fn matrix_only(dims: Dimensions) -> Result<()> {
+    ensure!(
+        dims.permuted.is_none(),
+        "digests only check matrix transposes, not other permutations of axes"
+    );
+    Ok(())
+}
+
+/// Builds up the [`Digests`] of an input out of the bytes a strategy reads
+/// anyway, so checking its outputs costs only one pass over each of them.
+///
+/// Every row has to be fed in column order and every column in row order,
+/// which is what reading whole rows from the top, or slices of them in
+/// passes from the left, does.
+#[derive(Debug, Clone)]
+pub struct Digester {
+    digests: Digests,
+    elem_size: usize,
+    /// bytes fed so far, and out of how many
+    fed: u64,
+    size: u64,
+}
+
+impl Digester {
+    pub fn new(dims: Dimensions) -> Result<Self> {
+        matrix_only(dims)?;
+        Ok(Digester::of_shape(
+            dims.size,
+            dims.rows,
+            dims.cols,
+            dims.elem_size,
+        ))
+    }
+
+    fn of_shape(size: u64, rows: usize, cols: usize, elem_size: usize) -> Self {
+        Digester {
+            digests: Digests {
+                rows: vec![FNV_OFFSET_BASIS; rows],
+                cols: vec![FNV_OFFSET_BASIS; cols],
+            },
+            elem_size,
+            fed: 0,
+            size,
+        }
+    }
+
+    /// Digest `bytes`, whole elements starting at column `col` of row `row`
+    /// and running on into the next rows if there are more than fit.
+    pub fn feed(&mut self, mut row: usize, mut col: usize, bytes: &[u8]) {
+        let Digests { rows, cols } = &mut self.digests;
+        for element in bytes.chunks_exact(self.elem_size) {
+            for byte in element {
+                rows[row] = fnv1a(rows[row], *byte);
+                cols[col] = fnv1a(cols[col], *byte);
+            }
+            col += 1;
+            if col == cols.len() {
+                col = 0;
+                row += 1;
+            }
+        }
+        self.fed += bytes.len() as u64;
+    }
+
+    /// The digests, if all of the input was fed.
+    pub fn finish(self) -> Option<Digests> {
+        (self.fed == self.size).then_some(self.digests)
+    }
+}
+
+/// Read until `buf` is full or the reader is exhausted.
+pub(crate) fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+fn read_window(file: &File, window_start: u64) -> Result<Vec<u8>> {
+    let mut window = vec![0u8; 2 * WINDOW_RADIUS as usize + 1];
+    let mut filled = 0;
+    while filled < window.len() {
+        match file.read_at(&mut window[filled..], window_start + filled as u64)? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    window.truncate(filled);
+    Ok(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern_byte;
+    use std::io::Write;
+
+    fn temp_file(name: &str, contents: &[u8]) -> File {
+        let path = std::env::temp_dir().join(format!("matrix_transposer_verify_{name}"));
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(contents).unwrap();
+        std::fs::remove_file(path).unwrap();
+        file
+    }
+
+    #[test]
+    fn oracle_checks_every_byte() {
+        let dims @ Dimensions {
+            size, rows, cols, ..
+        } = Dimensions::from_log2_size(21);
+        let mut transposed = vec![0u8; size as usize];
+        for i in 0..rows {
+            for j in 0..cols {
+                transposed[j * rows + i] = pattern_byte((i * cols + j) as u64);
+            }
+        }
+        let correct = temp_file("oracle_correct", &transposed);
+        assert_eq!(
+            verify_with_oracle(dims, &correct, pattern_byte).unwrap(),
+            None
+        );
+
+        let untransposed: Vec<u8> = (0..size).map(pattern_byte).collect();
+        let untransposed = temp_file("oracle_untransposed", &untransposed);
+        let mismatch = verify_with_oracle(dims, &untransposed, pattern_byte)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.offset, 1);
+
+        let last = size as usize - 1;
+        transposed[last] ^= 1;
+        let wrong = temp_file("oracle_wrong", &transposed);
+        let mismatch = verify_with_oracle(dims, &wrong, pattern_byte)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.offset, last as u64);
+        assert_eq!(mismatch.output_index, [cols - 1, rows - 1]);
+        assert_eq!(mismatch.input_index, [rows - 1, cols - 1]);
+        assert_eq!(mismatch.expected.len(), WINDOW_RADIUS as usize + 1);
+
+        transposed[last] ^= 1;
+        let long = temp_file("oracle_long", &[transposed.as_slice(), b"x"].concat());
+        let mismatch = verify_with_oracle(dims, &long, pattern_byte)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.offset, size);
+
+        let short = temp_file("oracle_short", &transposed[..100]);
+        let mismatch = verify_with_oracle(dims, &short, pattern_byte)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.offset, 100);
+        assert_eq!(mismatch.actual.len(), WINDOW_RADIUS as usize);
+    }
+
+    #[test]
+    fn locates_mismatches_in_permuted_arrays() {
+        let dims = Dimensions::permuted(&[2, 3, 4], &[1, 0, 2], 2).unwrap();
+        let mut permuted: Vec<u8> = (0..dims.size)
+            .map(|offset| pattern_byte(dims.source(offset / 2) * 2 + offset % 2))
+            .collect();
+        let correct = temp_file("oracle_permuted", &permuted);
+        assert_eq!(
+            verify_with_oracle(dims, &correct, pattern_byte).unwrap(),
+            None
+        );
+
+        // element [2, 1, 3] of the 3x2x4 output
+        let offset = (2 * 8 + 4 + 3) * 2 + 1;
+        permuted[offset] ^= 1;
+        let wrong = temp_file("oracle_permuted_wrong", &permuted);
+        let mismatch = verify_with_oracle(dims, &wrong, pattern_byte)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mismatch.offset, offset as u64);
+        assert_eq!(mismatch.output_index, [2, 1, 3]);
+        assert_eq!(mismatch.input_index, [1, 2, 3]);
+        assert!(mismatch.to_string().starts_with(
+            "first mismatch at byte 47: element [2, 1, 3] of the output ([1, 2, 3] of the input)"
+        ));
+    }
+
+    #[test]
+    fn digests_catch_swapped_rows() {
+        let dims @ Dimensions {
+            size, rows, cols, ..
+        } = Dimensions::from_log2_size(13);
+        let input: Vec<u8> = (0..size).map(|i| (i * 7 % 256) as u8).collect();
+        let mut transposed = vec![0u8; size as usize];
+        for i in 0..rows {
+            for j in 0..cols {
+                transposed[j * rows + i] = input[i * cols + j];
+            }
+        }
+        let digests = Digests::of_input(dims, &temp_file("digest_input", &input)).unwrap();
+        let correct = temp_file("digest_correct", &transposed);
+        assert_eq!(digests.verify_transposed(dims, &correct).unwrap(), None);
+
+        // swapping two whole output rows keeps every output column's bytes
+        // the same set, but not in the same order
+        let (first, second) = transposed.split_at_mut(rows);
+        first.swap_with_slice(&mut second[..rows]);
+        let swapped = temp_file("digest_swapped", &transposed);
+        let mismatch = digests.verify_transposed(dims, &swapped).unwrap().unwrap();
+        assert_eq!(
+            mismatch,
+            DigestMismatch::Content {
+                first_bad_row: Some(0),
+                bad_rows: 2,
+                first_bad_col: Some(0),
+                bad_cols: rows,
+            }
+        );
+
+        let short = temp_file("digest_short", &transposed[1..]);
+        let mismatch = digests.verify_transposed(dims, &short).unwrap().unwrap();
+        assert_eq!(
+            mismatch,
+            DigestMismatch::Length {
+                expected: size,
+                actual: size - 1
+            }
+        );
+    }
+
+    #[test]
+    fn digesters_match_a_separate_pass() {
+        let dims @ Dimensions {
+            size, rows, cols, ..
+        } = Dimensions::from_log2_size(12).with_elem_size(4);
+        let input: Vec<u8> = (0..size).map(|i| (i * 7 % 256) as u8).collect();
+        let digests = Digests::of_input(dims, &temp_file("digester_input", &input)).unwrap();
+
+        // like the buffered solution, three columns of every row at a time
+        let row_len = cols * 4;
+        let mut digester = Digester::new(dims).unwrap();
+        for first_col in (0..cols).step_by(3) {
+            let pass_len = 3.min(cols - first_col) * 4;
+            for row in 0..rows {
+                let start = row * row_len + first_col * 4;
+                digester.feed(row, first_col, &input[start..start + pass_len]);
+            }
+        }
+        assert_eq!(digester.finish(), Some(digests));
+
+        let mut digester = Digester::new(dims).unwrap();
+        digester.feed(0, 0, &input[..row_len]);
+        assert_eq!(digester.finish(), None);
+    }
+}
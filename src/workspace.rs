@@ -0,0 +1,125 @@
+use crate::Strategy;
+use crate::checkpoint::Checkpoints;
+use crate::strategies::Tiling;
+use anyhow::{Context, Result};
+use std::fs::{File, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Where a run puts its files: the input and every output go in `dir`, and
+/// scratch files in `temp_dir`. Pointing these at different mounts is how
+/// to benchmark them, and giving instances their own `dir` lets several run
+/// at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workspace {
+    pub dir: PathBuf,
+    pub temp_dir: PathBuf,
+    /// write outputs under a temporary name and rename them into place once
+    /// they're complete, so a file at an output path is never half written
+    pub atomic_outputs: bool,
+    /// whether strategies that can keep a journal of their progress do
+    pub checkpoints: Checkpoints,
+    /// how much of the matrix the buffered strategy holds in memory at once
+    pub tiling: Tiling,
+}
+
+impl Default for Workspace {
+    /// The cwd, and the system's temp dir.
+    fn default() -> Self {
+        Workspace::new(".", None)
+    }
+}
+
+impl Workspace {
+    /// Scratch files go in the system's temp dir unless there's a `temp_dir`.
+    pub fn new(dir: impl Into<PathBuf>, temp_dir: Option<PathBuf>) -> Self {
+        Workspace {
+            dir: dir.into(),
+            temp_dir: temp_dir.unwrap_or_else(std::env::temp_dir),
+            atomic_outputs: false,
+            checkpoints: Checkpoints::Off,
+            tiling: Tiling::default(),
+        }
+    }
+
+    /// The same workspace, with or without [atomic
+    /// outputs](Self::atomic_outputs).
+    pub fn with_atomic_outputs(self, atomic_outputs: bool) -> Self {
+        Workspace {
+            atomic_outputs,
+            ..self
+        }
+    }
+
+    /// Make sure both directories exist.
+    pub fn create(&self) -> Result<()> {
+        for dir in [&self.dir, &self.temp_dir] {
+            create_dir_all(dir).with_context(|| format!("couldn't create {}", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Where generated inputs go.
+    pub fn input_path(&self) -> PathBuf {
+        self.dir.join("input_file.md")
+    }
+
+    /// Where the index of a delimited text input goes, see
+    /// [`index`](crate::delimited::index).
+    pub fn text_index_path(&self) -> PathBuf {
+        self.dir.join("text_index.md")
+    }
+
+    /// Where `strategy`'s output of a `.npy` input is kept as a `.npy` file.
+    pub fn npy_path(&self, strategy: Strategy) -> PathBuf {
+        self.dir.join(format!("{}.npy", strategy.name()))
+    }
+
+    /// Where `strategy` writes its output, which no other strategy shares.
+    pub fn output_path(&self, strategy: Strategy) -> PathBuf {
+        self.dir.join(format!("{}.md", strategy.name()))
+    }
+
+    /// The same workspace, with `checkpoints`.
+    pub fn with_checkpoints(self, checkpoints: Checkpoints) -> Self {
+        Workspace {
+            checkpoints,
+            ..self
+        }
+    }
+
+    /// The same workspace, with `tiling`.
+    pub fn with_tiling(self, tiling: Tiling) -> Self {
+        Workspace { tiling, ..self }
+    }
+
+    /// Where `strategy` writes its output until it's published, when outputs
+    /// are [atomic](Self::atomic_outputs). It's in the same directory as the
+    /// output, so publishing is a rename within one file system.
+    pub fn partial_path(&self, strategy: Strategy) -> PathBuf {
+        self.dir.join(format!(".{}.md.partial", strategy.name()))
+    }
+
+    /// Move a complete, synced output from `partial_path` to `output_path`,
+    /// then sync the directory so the rename itself survives a crash.
+    pub fn publish(&self, partial_path: &Path, output_path: &Path) -> Result<()> {
+        std::fs::rename(partial_path, output_path).with_context(|| {
+            format!(
+                "couldn't move {} to {}",
+                partial_path.display(),
+                output_path.display()
+            )
+        })?;
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+
+    /// A scratch directory of one run only, so runs sharing a `temp_dir`,
+    /// in this process or another, don't trample each other's files.
+    pub fn scratch_dir(&self, name: &str) -> PathBuf {
+        static RUNS: AtomicU64 = AtomicU64::new(0);
+        let run = RUNS.fetch_add(1, Ordering::Relaxed);
+        self.temp_dir
+            .join(format!("{name}-{}-{run}", std::process::id()))
+    }
+}
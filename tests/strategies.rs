@@ -0,0 +1,218 @@
+use matrix_transposer::cancel::{Cancellation, is_cancelled};
+use matrix_transposer::generate::{Generator, GeneratorKind};
+use matrix_transposer::progress::Silent;
+use matrix_transposer::strategies::Tiling;
+use matrix_transposer::workspace::Workspace;
+use matrix_transposer::{Dimensions, Strategy as Transpose, reference_transpose};
+use proptest::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const PRIMES: [usize; 8] = [2, 3, 5, 7, 13, 31, 37, 61];
+
+/// Rows or cols: shrinks towards 1, and hits the degenerate and prime cases
+/// more often than a plain range would.
+fn side() -> impl Strategy<Value = usize> {
+    prop_oneof![
+        Just(1usize),
+        prop::sample::select(PRIMES.as_slice()),
+        1..=64usize,
+    ]
+}
+
+fn dims() -> impl Strategy<Value = Dimensions> {
+    (side(), side(), 1..=9usize)
+        .prop_map(|(rows, cols, elem_size)| Dimensions::new(rows, cols).with_elem_size(elem_size))
+}
+
+/// Bands that don't divide the rows, and passes that don't divide the
+/// columns, as well as the defaults.
+fn tiling() -> impl Strategy<Value = Tiling> {
+    prop_oneof![
+        Just(Tiling::default()),
+        (1..=8usize, prop::option::of(1..=8usize)).prop_map(|(band_rows, pass_cols)| Tiling {
+            band_rows,
+            pass_cols
+        }),
+    ]
+}
+
+/// A shape of up to 4 short axes, and a permutation of them.
+fn permuted() -> impl Strategy<Value = (Vec<usize>, Vec<usize>)> {
+    prop::collection::vec(1..=6usize, 1..=4).prop_flat_map(|shape| {
+        let axes: Vec<usize> = (0..shape.len()).collect();
+        (Just(shape), Just(axes).prop_shuffle())
+    })
+}
+
+/// Walks the output in order, working out the index of every input axis.
+fn reference_permute(
+    shape: &[usize],
+    permutation: &[usize],
+    elem_size: usize,
+    input: &[u8],
+) -> Vec<u8> {
+    let mut permuted = Vec::with_capacity(input.len());
+    let mut index = vec![0; shape.len()];
+    for _ in 0..input.len() / elem_size {
+        let from = index
+            .iter()
+            .zip(shape)
+            .fold(0, |from, (i, len)| from * len + i)
+            * elem_size;
+        permuted.extend_from_slice(&input[from..from + elem_size]);
+        for &axis in permutation.iter().rev() {
+            index[axis] += 1;
+            if index[axis] < shape[axis] {
+                break;
+            }
+            index[axis] = 0;
+        }
+    }
+    permuted
+}
+
+fn write_input(dims: Dimensions, seed: u64, path: &Path) -> Vec<u8> {
+    let generator = Generator {
+        kind: GeneratorKind::Random,
+        seed,
+        ..Generator::default()
+    };
+    let mut input = Vec::new();
+    generator.write(dims, &mut input, &Silent).unwrap();
+    let mut writer = BufWriter::new(File::create(path).unwrap());
+    writer.write_all(&input).unwrap();
+    writer.flush().unwrap();
+    input
+}
+
+fn read_output(mut output: File) -> Vec<u8> {
+    let mut contents = Vec::new();
+    output.seek(SeekFrom::Start(0)).unwrap();
+    output.read_to_end(&mut contents).unwrap();
+    contents
+}
+
+proptest! {
+    #[test]
+    fn every_strategy_transposes_every_shape(
+        dims in dims(),
+        seed in any::<u64>(),
+        atomic in any::<bool>(),
+        tiling in tiling(),
+    ) {
+        let workspace = Workspace::new(std::env::temp_dir().join("matrix_transposer_proptest"), None)
+            .with_atomic_outputs(atomic)
+            .with_tiling(tiling);
+        workspace.create().unwrap();
+
+        let input_path = workspace.input_path();
+        let input = write_input(dims, seed, &input_path);
+        let expected = reference_transpose(dims, &input);
+        for strategy in Transpose::ALL {
+            let (output, _) = strategy
+                .run(dims, &input_path, &workspace, &Silent, &Cancellation::new()).unwrap();
+            let actual = read_output(output);
+            std::fs::remove_file(workspace.output_path(strategy)).unwrap();
+            prop_assert!(!workspace.partial_path(strategy).exists());
+            prop_assert!(
+                actual == expected,
+                "{} got a different transpose of a {dims:?} input",
+                strategy.name()
+            );
+        }
+    }
+
+    #[test]
+    fn permuting_strategies_permute_every_shape(
+        (shape, permutation) in permuted(),
+        elem_size in 1..=4usize,
+        seed in any::<u64>(),
+        tiling in tiling(),
+    ) {
+        let workspace = Workspace::new(std::env::temp_dir().join("matrix_transposer_permute"), None)
+            .with_tiling(tiling);
+        workspace.create().unwrap();
+
+        let dims = Dimensions::permuted(&shape, &permutation, elem_size).unwrap();
+        let input_path = workspace.input_path();
+        let input = write_input(dims, seed, &input_path);
+        let expected = reference_permute(&shape, &permutation, elem_size, &input);
+        for strategy in Transpose::ALL {
+            let result = strategy.run(dims, &input_path, &workspace, &Silent, &Cancellation::new());
+            if dims.permuted.is_some() && !strategy.can_permute() {
+                prop_assert!(result.is_err());
+                continue;
+            }
+            let actual = read_output(result.unwrap().0);
+            std::fs::remove_file(workspace.output_path(strategy)).unwrap();
+            prop_assert!(
+                actual == expected,
+                "{} got a different permute of a {shape:?} input by {permutation:?}",
+                strategy.name()
+            );
+        }
+    }
+}
+
+#[test]
+fn every_strategy_starts_at_the_input_offset() {
+    let workspace = Workspace::new(std::env::temp_dir().join("matrix_transposer_offset"), None)
+        .with_tiling(Tiling {
+            band_rows: 3,
+            pass_cols: Some(4),
+        });
+    workspace.create().unwrap();
+    let input_path = workspace.input_path();
+    let header = b"not part of the matrix";
+    let cases = [
+        Dimensions::new(7, 11).with_elem_size(3),
+        Dimensions::permuted(&[3, 4, 5], &[0, 2, 1], 2).unwrap(),
+    ];
+    for dims in cases {
+        let input = write_input(dims, 1, &input_path);
+        let expected = read_output({
+            let (output, _) = Transpose::InMemory
+                .run(dims, &input_path, &workspace, &Silent, &Cancellation::new())
+                .unwrap();
+            output
+        });
+        std::fs::write(&input_path, [header.as_slice(), &input].concat()).unwrap();
+        let dims = dims.with_input_offset(header.len() as u64);
+        for strategy in Transpose::ALL
+            .into_iter()
+            .filter(|strategy| dims.permuted.is_none() || strategy.can_permute())
+        {
+            let (output, _) = strategy
+                .run(dims, &input_path, &workspace, &Silent, &Cancellation::new())
+                .unwrap();
+            assert!(
+                read_output(output) == expected,
+                "{} didn't skip the first {} bytes of a {dims:?} input",
+                strategy.name(),
+                header.len()
+            );
+            std::fs::remove_file(workspace.output_path(strategy)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn every_strategy_stops_once_cancelled() {
+    let workspace = Workspace::new(std::env::temp_dir().join("matrix_transposer_cancel"), None);
+    workspace.create().unwrap();
+    let dims = Dimensions::new(16, 8);
+    let input_path = workspace.input_path();
+    write_input(dims, 0, &input_path);
+
+    let cancel = Cancellation::new();
+    cancel.cancel();
+    for strategy in Transpose::ALL {
+        let error = strategy
+            .run(dims, &input_path, &workspace, &Silent, &cancel)
+            .unwrap_err();
+        assert!(is_cancelled(&error), "{}: {error:#}", strategy.name());
+        let _ = std::fs::remove_file(workspace.output_path(strategy));
+    }
+}